@@ -5,6 +5,7 @@ use std::io::BufReader;
 use std::io::SeekFrom;
 
 fn main() -> Result<()> {
+    pretty_env_logger::init();
     let mut file = BufReader::new(File::open("inputs/day1.txt")?);
     part1(&mut file);
     file.seek(SeekFrom::Start(0))?;
@@ -1,52 +1,41 @@
-use anyhow::{anyhow, bail, Result};
-use itertools::Itertools;
-use std::fs;
+use anyhow::{anyhow, Context, Result};
+use intcode::{solve, Image, Program, Source, Target, Unknown};
+use std::collections::VecDeque;
 
 fn main() -> Result<()> {
-    let input = fs::read_to_string("inputs/day2.txt")?;
-    let intcode: Vec<usize> = input
-        .split(',')
-        .map(|opcode| opcode.parse().unwrap())
-        .collect();
-    let result = process_intcode(&intcode, 12, 2)?;
+    pretty_env_logger::init();
+    let image = Image::load("inputs/day2.txt")?;
+    let result = run_with_noun_and_verb(&image.program(&[]), 12, 2)?;
     println!("{}", result[0]);
-    let result = bruteforce(&intcode, 19_690_720).unwrap();
+    let result = find_noun_and_verb(&image.intcode, 19_690_720).unwrap();
     println!("{}", result.0 * 100 + result.1);
     Ok(())
 }
 
-fn process_intcode(intcode: &[usize], noun: usize, verb: usize) -> Result<Vec<usize>> {
-    let mut intcode = intcode.to_vec();
-    intcode[1] = noun;
-    intcode[2] = verb;
-    let mut ip = 0;
-    while let Some(opcode) = intcode.get(ip) {
-        match opcode {
-            1 => {
-                let operand1 = intcode[ip + 1];
-                let operand2 = intcode[ip + 2];
-                let output = intcode[ip + 3];
-                intcode[output] = intcode[operand1] + intcode[operand2];
-                ip += 4;
-            }
-            2 => {
-                let operand1 = intcode[ip + 1];
-                let operand2 = intcode[ip + 2];
-                let output = intcode[ip + 3];
-                intcode[output] = intcode[operand1] * intcode[operand2];
-                ip += 4;
-            }
-            99 => return Ok(intcode),
-            _ => bail!("invalid opcode: `{}`", opcode),
-        }
-    }
-    Err(anyhow!("no end found"))
+#[cfg(test)]
+fn process_intcode(intcode: &[i128], noun: i128, verb: i128) -> Result<Vec<i128>> {
+    run_with_noun_and_verb(&Program::new(intcode, &[]), noun, verb)
 }
 
-fn bruteforce(intcode: &[usize], output: usize) -> Option<(usize, usize)> {
-    (0..99)
-        .cartesian_product(0..99)
-        .find(|(noun, verb)| process_intcode(intcode, *noun, *verb).unwrap()[0] == output)
+fn run_with_noun_and_verb(program: &Program, noun: i128, verb: i128) -> Result<Vec<i128>> {
+    let mut program = program.fork();
+    program.intcode[1] = noun;
+    program.intcode[2] = verb;
+    program
+        .run(&mut VecDeque::new())
+        .map_err(|error| anyhow!(program.explain(&error)))?;
+    program.intcode.to_vec().context("memory too large to copy")
+}
+
+fn find_noun_and_verb(intcode: &[i128], output: i128) -> Option<(i128, i128)> {
+    let unknown = |name: &str, address| Unknown {
+        name: name.to_string(),
+        source: Source::Memory(address),
+        range: 0..=99,
+    };
+    let unknowns = [unknown("noun", 1), unknown("verb", 2)];
+    let solution = solve(intcode, &[], &unknowns, Target::Memory(0), output)?;
+    Some((solution.values[0], solution.values[1]))
 }
 
 #[test]
@@ -5,6 +5,7 @@ use std::ops::RangeInclusive;
 use std::str::FromStr;
 
 fn main() -> Result<()> {
+    pretty_env_logger::init();
     let input = fs::read_to_string("inputs/day3.txt")?;
     let wires = input
         .lines()
@@ -1,6 +1,7 @@
 use itertools::Itertools;
 
 fn main() {
+    pretty_env_logger::init();
     let result = (156_218..=652_527)
         .filter(|&password| is_valid_password(password))
         .count();
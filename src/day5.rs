@@ -1,203 +1,63 @@
 use anyhow::{anyhow, bail, Result};
-use std::fs;
-use std::io::Write;
-
-fn read_from_stdin() -> Option<i32> {
-    let mut input = String::new();
-    print!("input: ");
-    std::io::stdout().flush().unwrap();
-    std::io::stdin()
-        .read_line(&mut input)
-        .expect("failed to read from stdin");
-    Some(input.trim().parse().expect("not a number"))
-}
+use intcode::{Image, InputDevice, InputFn, LineReader, Program, ReplayError, Session};
 
 fn main() -> Result<()> {
-    let input = fs::read_to_string("inputs/day5.txt")?;
-    let intcode: Vec<i32> = input
-        .trim()
-        .split(',')
-        .map(|opcode| opcode.parse().unwrap())
-        .collect();
+    pretty_env_logger::init();
+    let image = Image::load("inputs/day5.txt")?;
+    let mut program = image.program(&[]);
+    let mut stdin = LineReader::stdin("input: ");
     let mut output = Vec::new();
-    process_intcode(
-        &intcode,
-        &mut std::iter::from_fn(read_from_stdin),
-        &mut output,
-    )?;
-    println!("{:?}", output);
-    Ok(())
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum ParameterMode {
-    Position,
-    Immediate,
-}
-
-impl Default for ParameterMode {
-    fn default() -> Self {
-        ParameterMode::Position
-    }
-}
-
-impl ParameterMode {
-    fn new(mode: u32) -> Self {
-        match mode {
-            0 => ParameterMode::Position,
-            1 => ParameterMode::Immediate,
-            _ => panic!("unknown parameter mode"),
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => run_program(
+            &mut program,
+            &mut std::iter::from_fn(|| stdin.read()),
+            &mut output,
+        )?,
+        ["--record", path] => {
+            let mut session = Session::default();
+            let result = program.record(&mut InputFn(|| stdin.read()), &mut output, &mut session);
+            session.save(path)?;
+            result.map_err(|error| anyhow!(program.explain(&error)))?;
         }
-    }
-
-    fn get(self, intcode: &[i32], ip: usize) -> i32 {
-        match self {
-            ParameterMode::Position => intcode[intcode[ip] as usize],
-            ParameterMode::Immediate => intcode[ip],
+        ["--replay", path] => {
+            output = program
+                .replay(&Session::load(path)?)
+                .map_err(|error| match error {
+                    ReplayError::Vm(error) => anyhow!(program.explain(&error)),
+                    error => error.into(),
+                })?;
+            println!("replay of {} matched", path);
         }
+        _ => bail!("usage: day5 [--record <log> | --replay <log>]"),
     }
-
-    fn get_mut<'a>(&self, intcode: &'a mut [i32], ip: usize) -> &'a mut i32 {
-        &mut intcode[intcode[ip] as usize]
-    }
-}
-
-#[derive(Clone, Debug, PartialEq)]
-struct Parameters(Vec<ParameterMode>);
-
-impl Parameters {
-    fn get(&self, index: usize, intcode: &[i32], ip: usize) -> i32 {
-        self.0
-            .get(index)
-            .copied()
-            .unwrap_or_default()
-            .get(intcode, ip + index + 1)
-    }
-
-    fn get_mut<'a>(&self, index: usize, intcode: &'a mut [i32], ip: usize) -> &'a mut i32 {
-        ParameterMode::Position.get_mut(intcode, ip + index + 1)
-    }
-}
-
-struct Opcode {
-    code: usize,
-    parameters: Parameters,
-}
-
-impl Opcode {
-    fn new(word: i32) -> Self {
-        let mut digits = word.to_string().chars().rev().collect::<Vec<_>>();
-        let code = digits
-            .drain(0..2.min(digits.len()))
-            .collect::<String>()
-            .chars()
-            .rev()
-            .collect::<String>()
-            .parse()
-            .unwrap();
-        let parameters = Parameters(
-            digits
-                .iter()
-                .map(|digit| digit.to_digit(10).unwrap())
-                .map(ParameterMode::new)
-                .collect(),
-        );
-        Opcode { code, parameters }
-    }
-}
-
-fn add(parameters: &Parameters, intcode: &mut [i32], ip: &mut usize) {
-    let operand1 = parameters.get(0, intcode, *ip);
-    let operand2 = parameters.get(1, intcode, *ip);
-    let output = parameters.get_mut(2, intcode, *ip);
-    *output = operand1 + operand2;
-    *ip += 4;
-}
-
-fn mul(parameters: &Parameters, intcode: &mut [i32], ip: &mut usize) {
-    let operand1 = parameters.get(0, intcode, *ip);
-    let operand2 = parameters.get(1, intcode, *ip);
-    let output = parameters.get_mut(2, intcode, *ip);
-    *output = operand1 * operand2;
-    *ip += 4;
-}
-
-fn input(
-    stdin: &mut impl Iterator<Item = i32>,
-    parameters: &Parameters,
-    intcode: &mut [i32],
-    ip: &mut usize,
-) {
-    let output = parameters.get_mut(0, intcode, *ip);
-    let input = stdin.next().expect("no input");
-    *output = input;
-    *ip += 2;
-}
-
-fn output(stdout: &mut Vec<i32>, parameters: &Parameters, intcode: &mut [i32], ip: &mut usize) {
-    let operand1 = parameters.get(0, intcode, *ip);
-    stdout.push(operand1);
-    *ip += 2;
-}
-
-fn jump_if(parameters: &Parameters, intcode: &mut [i32], ip: &mut usize) {
-    let condition = parameters.get(0, intcode, *ip);
-    let jump_addr = parameters.get(1, intcode, *ip);
-    if condition != 0 {
-        *ip = jump_addr as usize;
-    } else {
-        *ip += 3;
-    }
-}
-
-fn jump_unless(parameters: &Parameters, intcode: &mut [i32], ip: &mut usize) {
-    let condition = parameters.get(0, intcode, *ip);
-    let jump_addr = parameters.get(1, intcode, *ip);
-    if condition == 0 {
-        *ip = jump_addr as usize;
-    } else {
-        *ip += 3;
-    }
-}
-
-fn is_less_than(parameters: &Parameters, intcode: &mut [i32], ip: &mut usize) {
-    let operand1 = parameters.get(0, intcode, *ip);
-    let operand2 = parameters.get(1, intcode, *ip);
-    let output = parameters.get_mut(2, intcode, *ip);
-    *output = if operand1 < operand2 { 1 } else { 0 };
-    *ip += 4;
+    println!("{:?}", output);
+    Ok(())
 }
 
-fn is_equal(parameters: &Parameters, intcode: &mut [i32], ip: &mut usize) {
-    let operand1 = parameters.get(0, intcode, *ip);
-    let operand2 = parameters.get(1, intcode, *ip);
-    let output = parameters.get_mut(2, intcode, *ip);
-    *output = if operand1 == operand2 { 1 } else { 0 };
-    *ip += 4;
+#[cfg(test)]
+fn process_intcode(
+    intcode: &[i128],
+    stdin: &mut impl Iterator<Item = i128>,
+    stdout: &mut Vec<i128>,
+) -> Result<()> {
+    run_program(&mut Program::new(intcode, &[]), stdin, stdout)
 }
 
-fn process_intcode(
-    intcode: &[i32],
-    stdin: &mut impl Iterator<Item = i32>,
-    stdout: &mut Vec<i32>,
+fn run_program(
+    program: &mut Program,
+    stdin: &mut impl Iterator<Item = i128>,
+    stdout: &mut Vec<i128>,
 ) -> Result<()> {
-    let mut intcode = intcode.to_vec();
-    let mut ip = 0;
-    while let Some(opcode) = intcode.get(ip).copied().map(Opcode::new) {
-        match opcode.code {
-            1 => add(&opcode.parameters, &mut intcode, &mut ip),
-            2 => mul(&opcode.parameters, &mut intcode, &mut ip),
-            3 => input(stdin, &opcode.parameters, &mut intcode, &mut ip),
-            4 => output(stdout, &opcode.parameters, &mut intcode, &mut ip),
-            5 => jump_if(&opcode.parameters, &mut intcode, &mut ip),
-            6 => jump_unless(&opcode.parameters, &mut intcode, &mut ip),
-            7 => is_less_than(&opcode.parameters, &mut intcode, &mut ip),
-            8 => is_equal(&opcode.parameters, &mut intcode, &mut ip),
-            99 => return Ok(()),
-            _ => bail!("invalid opcode: `{}`", opcode.code),
-        }
-    }
-    Err(anyhow!("no end found"))
+    program
+        .run_with(&mut InputFn(|| stdin.next()), stdout)
+        .map_err(|error| anyhow!(program.explain(&error)))?;
+    Ok(())
 }
 
 #[test]
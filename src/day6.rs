@@ -2,6 +2,7 @@ use anyhow::{anyhow, Result};
 use std::collections::HashMap;
 
 fn main() -> Result<()> {
+    pretty_env_logger::init();
     let input = std::fs::read_to_string("inputs/day6.txt")?;
     let orbits = parse_orbits(&input)?;
     let result = count_orbits(&orbits);
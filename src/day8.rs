@@ -83,6 +83,7 @@ impl FromStr for Image {
 }
 
 fn main() -> Result<()> {
+    pretty_env_logger::init();
     let input = std::fs::read_to_string("inputs/day8.txt")?;
     let image = Image::from_str(input.trim())?;
     let min_zero_layer = image
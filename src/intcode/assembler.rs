@@ -0,0 +1,300 @@
+use crate::opcode::{code_from_mnemonic, Opcode, ParameterMode, Parameters};
+use crate::symbols::SourceMap;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(i128),
+    Label(String, i128),
+}
+
+enum Item {
+    Instruction(Opcode, Vec<Value>),
+    Data(Vec<Value>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match self {
+            Item::Instruction(_, operands) => operands.len() + 1,
+            Item::Data(values) => values.len(),
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i128>, AsmError> {
+    assemble_with_symbols(source).map(|(intcode, _)| intcode)
+}
+
+// Also returns the labels and the source line of every instruction and `db`.
+pub fn assemble_with_symbols(source: &str) -> Result<(Vec<i128>, SourceMap), AsmError> {
+    let mut labels = HashMap::new();
+    let mut symbols = SourceMap::default();
+    let mut items = Vec::new();
+    let mut address = 0;
+    for (index, line) in source.lines().enumerate() {
+        let error = |message| AsmError {
+            line: index + 1,
+            message,
+        };
+        let mut text = line.split(';').next().unwrap_or_default().trim();
+        while let Some((label, rest)) = split_label(text) {
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(error(format!("duplicate label `{}`", label)));
+            }
+            symbols
+                .labels
+                .entry(address)
+                .or_insert_with(|| label.to_string());
+            text = rest;
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let item = parse_item(text).map_err(error)?;
+        address += item.size();
+        items.push((index + 1, item));
+    }
+    let mut intcode = Vec::with_capacity(address);
+    for (line, item) in items {
+        symbols.lines.insert(intcode.len(), line);
+        symbols
+            .ends
+            .insert(intcode.len(), intcode.len() + item.size());
+        let resolve = |value: &Value| match value {
+            Value::Number(number) => Ok(*number),
+            Value::Label(label, offset) => labels
+                .get(label)
+                .map(|&address| address as i128 + offset)
+                .ok_or_else(|| AsmError {
+                    line,
+                    message: format!("unknown label `{}`", label),
+                }),
+        };
+        let values = match &item {
+            Item::Instruction(opcode, operands) => {
+                intcode.push(Opcode::encode(opcode.code, &opcode.parameters.0));
+                operands
+            }
+            Item::Data(values) => values,
+        };
+        for value in values {
+            intcode.push(resolve(value)?);
+        }
+    }
+    Ok((intcode, symbols))
+}
+
+pub(crate) fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let colon = text.find(':')?;
+    let label = text[..colon].trim();
+    if is_identifier(label) {
+        Some((label, text[colon + 1..].trim()))
+    } else {
+        None
+    }
+}
+
+fn parse_item(text: &str) -> Result<Item, String> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    };
+    let arguments = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    if mnemonic == "db" {
+        if arguments.is_empty() {
+            return Err("`db` needs at least one value".to_string());
+        }
+        return Ok(Item::Data(
+            arguments
+                .into_iter()
+                .map(parse_value)
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    let code =
+        code_from_mnemonic(mnemonic).ok_or_else(|| format!("unknown mnemonic `{}`", mnemonic))?;
+    let (modes, operands): (Vec<_>, Vec<_>) = arguments
+        .into_iter()
+        .map(parse_operand)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .unzip();
+    let opcode = Opcode {
+        code,
+        parameters: Parameters::new(&modes),
+    };
+    let expected = opcode.parameter_count().unwrap_or_default();
+    if operands.len() != expected {
+        return Err(format!(
+            "`{}` expects {} operand(s), found {}",
+            mnemonic,
+            expected,
+            operands.len()
+        ));
+    }
+    if let Some(index) = opcode.written_parameter() {
+        if opcode.parameters.mode(index) == ParameterMode::Immediate {
+            return Err(format!(
+                "operand {} of `{}` is written and cannot be immediate",
+                index + 1,
+                mnemonic
+            ));
+        }
+    }
+    Ok(Item::Instruction(opcode, operands))
+}
+
+fn parse_operand(text: &str) -> Result<(ParameterMode, Value), String> {
+    if let Some(value) = text.strip_prefix('#') {
+        Ok((ParameterMode::Immediate, parse_value(value)?))
+    } else if let Some(address) = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+    {
+        Ok((ParameterMode::Position, parse_value(address)?))
+    } else if text == "rb" {
+        Ok((ParameterMode::Relative, Value::Number(0)))
+    } else if let Some(offset) = text.strip_prefix("rb+") {
+        Ok((ParameterMode::Relative, parse_value(offset)?))
+    } else if text.starts_with("rb-") {
+        Ok((ParameterMode::Relative, parse_value(&text[2..])?))
+    } else {
+        Err(format!(
+            "expected `#value`, `[address]` or `rb+offset`, found `{}`",
+            text
+        ))
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if let Ok(number) = text.parse() {
+        return Ok(Value::Number(number));
+    }
+    let (label, offset) = match text
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '+' || c == '-')
+    {
+        Some((index, _)) => text.split_at(index),
+        None => (text, "0"),
+    };
+    let label = label.trim();
+    let offset = offset
+        .replace(char::is_whitespace, "")
+        .parse()
+        .map_err(|_| format!("invalid offset in `{}`", text))?;
+    if is_identifier(label) {
+        Ok(Value::Label(label.to_string(), offset))
+    } else {
+        Err(format!("invalid value `{}`", text))
+    }
+}
+
+#[test]
+fn test_assemble_position_mode() {
+    let source = "
+        in [9]          ; read a number
+        eq [9], [10], [9]
+        out [9]
+        hlt
+        db -1, 8
+    ";
+    assert_eq!(
+        assemble(source).unwrap(),
+        vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]
+    );
+}
+
+#[test]
+fn test_assemble_labels_and_relative_mode() {
+    let source = "
+        arb #buffer
+    loop: in rb
+        out rb+0
+        add [counter], #-1, [counter]
+        jt [counter], #loop
+        hlt
+    counter: db 2
+    buffer: db 0
+    ";
+    let intcode = assemble(source).unwrap();
+    assert_eq!(&intcode[..8], &[109, 15, 203, 0, 204, 0, 1001, 14]);
+    let mut program = crate::Program::new(&intcode, &[4, 5]);
+    assert_eq!(
+        program.run(&mut std::collections::VecDeque::new()).unwrap(),
+        vec![4, 5]
+    );
+}
+
+#[test]
+fn test_assemble_with_symbols() {
+    let source = "
+    start:
+        in [counter]
+    loop: add [counter], #-1, [counter]
+        jt [counter], #loop
+        hlt
+    counter: db 0
+    ";
+    let (intcode, symbols) = assemble_with_symbols(source).unwrap();
+    assert_eq!(intcode, assemble(source).unwrap());
+    assert_eq!(symbols.locate(0), "start (line 3)");
+    assert_eq!(symbols.locate(5), "loop+3 (line 4)");
+    assert_eq!(symbols.locate(8), "ip=8 (line 5)");
+    assert_eq!(symbols.locate(12), "ip=12");
+    assert_eq!(symbols.locate(10), "counter (line 7)");
+    assert_eq!(symbols.address("loop"), Some(2));
+}
+
+#[test]
+fn test_assemble_errors() {
+    assert_eq!(
+        assemble("hlt\nfoo #1"),
+        Err(AsmError {
+            line: 2,
+            message: "unknown mnemonic `foo`".to_string()
+        })
+    );
+    assert_eq!(assemble("add #1, #2, #3").unwrap_err().line, 1);
+    assert_eq!(assemble("\n\njt #1, #nowhere").unwrap_err().line, 3);
+    assert_eq!(assemble("out [1]\nout [1], [2]").unwrap_err().line, 2);
+    assert_eq!(assemble("a: hlt\na: hlt").unwrap_err().line, 2);
+}
+
+#[test]
+fn test_parse_value() {
+    assert_eq!(parse_value("-3"), Ok(Value::Number(-3)));
+    assert_eq!(parse_value("x+2"), Ok(Value::Label("x".to_string(), 2)));
+    assert_eq!(parse_value("x - 1"), Ok(Value::Label("x".to_string(), -1)));
+    assert!(parse_value("").is_err());
+    assert!(parse_value("1x").is_err());
+}
@@ -0,0 +1,110 @@
+use crate::program::Program;
+use crate::word::Word;
+use std::time::{Duration, Instant};
+
+// reading the clock on every instruction would dominate the interpreter loop
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Budget {
+    pub max_instructions: Option<u64>,
+    pub deadline: Option<Instant>,
+    // the interval in which the clock was last read; compiled blocks advance
+    // the count by more than one, so crossing into a new interval is what
+    // triggers the next read
+    last_check: Option<u64>,
+}
+
+impl Budget {
+    pub fn is_exhausted(&mut self, instructions: u64) -> bool {
+        if self
+            .max_instructions
+            .is_some_and(|max_instructions| instructions >= max_instructions)
+        {
+            return true;
+        }
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return false,
+        };
+        let interval = instructions / DEADLINE_CHECK_INTERVAL;
+        if self.last_check == Some(interval) {
+            return false;
+        }
+        self.last_check = Some(interval);
+        Instant::now() >= deadline
+    }
+
+    // Whether the instruction limit allows running until `instructions`
+    // have been executed, as checked before a whole compiled block.
+    pub fn fits(&self, instructions: u64) -> bool {
+        self.max_instructions
+            .is_none_or(|max_instructions| instructions <= max_instructions)
+    }
+}
+
+impl<W: Word> Program<W> {
+    pub fn with_instruction_budget(mut self, instructions: u64) -> Self {
+        self.set_instruction_budget(instructions);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    pub fn set_instruction_budget(&mut self, instructions: u64) {
+        self.budget.max_instructions = Some(self.instructions + instructions);
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.budget.deadline = Some(Instant::now() + timeout);
+    }
+
+    pub fn clear_budget(&mut self) {
+        self.budget = Budget::default();
+    }
+}
+
+#[test]
+fn test_instruction_budget() {
+    use crate::RunState;
+
+    let mut program = Program::new(&[1105, 1, 0], &[]).with_instruction_budget(100);
+    assert_eq!(program.resume(), Ok(RunState::BudgetExhausted));
+    assert_eq!(program.instructions, 100);
+    assert_eq!(program.ip, 0);
+    program.set_instruction_budget(5);
+    assert_eq!(program.resume(), Ok(RunState::BudgetExhausted));
+    assert_eq!(program.instructions, 105);
+    assert_eq!(
+        program.next_output(&[]),
+        Err(crate::VmError::BudgetExhausted { ip: 0 })
+    );
+}
+
+#[test]
+fn test_timeout() {
+    use crate::RunState;
+
+    let mut program = Program::new(&[1105, 1, 0], &[]).with_timeout(Duration::from_millis(10));
+    assert_eq!(program.resume(), Ok(RunState::BudgetExhausted));
+    assert!(program.instructions > 0);
+    program.clear_budget();
+    program.set_instruction_budget(1);
+    assert_eq!(program.resume(), Ok(RunState::BudgetExhausted));
+}
+
+#[test]
+fn test_timeout_with_batched_instructions() {
+    let mut budget = Budget {
+        deadline: Some(Instant::now() + Duration::from_millis(10)),
+        ..Budget::default()
+    };
+    assert!(!budget.is_exhausted(1));
+    std::thread::sleep(Duration::from_millis(20));
+    // odd counts never land on a multiple of the check interval
+    let exhausted_at = (3..).step_by(2).find(|&count| budget.is_exhausted(count));
+    assert_eq!(exhausted_at, Some(DEADLINE_CHECK_INTERVAL + 1));
+}
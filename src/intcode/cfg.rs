@@ -0,0 +1,468 @@
+use crate::disassembler::{can_jump, disassemble_instruction, falls_through, jump_target};
+use crate::image::Image;
+use crate::opcode::{Opcode, ParameterMode};
+use crate::transpile::{self, code, ends_control_flow, leaders, size, Block};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Successor {
+    Block(usize),
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub words: Vec<i128>,
+    pub instructions: Vec<(usize, Opcode)>,
+    pub successors: Vec<Successor>,
+    pub call: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub entry: usize,
+    pub blocks: BTreeSet<usize>,
+    pub calls: BTreeSet<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loop {
+    pub header: usize,
+    pub blocks: BTreeSet<usize>,
+}
+
+// `labels` name addresses in the summary and the graph.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    pub entry: usize,
+    pub labels: BTreeMap<usize, String>,
+}
+
+fn operands<'a>(intcode: &'a [i128], address: usize, opcode: &Opcode) -> &'a [i128] {
+    &intcode[address + 1..address + size(opcode)]
+}
+
+impl ControlFlowGraph {
+    pub fn new(intcode: &[i128]) -> Self {
+        Self::from_entry(intcode, 0)
+    }
+
+    pub fn from_image(image: &Image) -> Self {
+        ControlFlowGraph {
+            labels: image.symbols.clone(),
+            ..Self::from_entry(&image.intcode, image.entry)
+        }
+    }
+
+    fn from_entry(intcode: &[i128], entry: usize) -> Self {
+        let code = code(intcode, &[entry]);
+        let leaders = leaders(intcode, &code, &[entry], ends_control_flow);
+        let mut blocks = BTreeMap::new();
+        for Block {
+            start,
+            end,
+            instructions,
+        } in transpile::blocks(intcode, &code, &leaders, ends_control_flow)
+        {
+            let (last, opcode) = *instructions.last().unwrap();
+            let jump = operands(intcode, last, &opcode);
+            let mut successors = Vec::new();
+            let mut call = None;
+            if matches!(opcode.code, 5 | 6) && can_jump(&opcode, jump) {
+                let target = jump_target(&opcode, jump);
+                successors.push(match target {
+                    Some(target) if code.contains(&target) => Successor::Block(target),
+                    _ => Successor::Unknown,
+                });
+                let pushes_return = instructions.iter().any(|&(at, opcode)| {
+                    operands(intcode, at, &opcode)
+                        .iter()
+                        .enumerate()
+                        .any(|(index, &operand)| {
+                            opcode.parameters.mode(index) == ParameterMode::Immediate
+                                && operand == end as i128
+                        })
+                });
+                if !falls_through(&opcode, jump) && pushes_return {
+                    call = target;
+                }
+            }
+            if falls_through(&opcode, jump) && code.contains(&end) {
+                successors.push(Successor::Block(end));
+            }
+            blocks.insert(
+                start,
+                BasicBlock {
+                    start,
+                    end,
+                    words: intcode[start..end].to_vec(),
+                    instructions,
+                    successors,
+                    call,
+                },
+            );
+        }
+        ControlFlowGraph {
+            blocks,
+            entry,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    // `12` or `12 (loop_start)`
+    fn name(&self, address: usize) -> String {
+        match self.labels.get(&address) {
+            Some(label) => format!("{} ({})", address, label),
+            None => address.to_string(),
+        }
+    }
+
+    fn intraprocedural_successors(&self, block: &BasicBlock) -> Vec<usize> {
+        match block.call {
+            Some(_) => Some(block.end)
+                .filter(|end| self.blocks.contains_key(end))
+                .into_iter()
+                .collect(),
+            None => block
+                .successors
+                .iter()
+                .filter_map(|successor| match successor {
+                    Successor::Block(start) => Some(*start),
+                    Successor::Unknown => None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn indirect_jumps(&self) -> Vec<usize> {
+        self.blocks
+            .values()
+            .filter(|block| block.successors.contains(&Successor::Unknown))
+            .filter_map(|block| block.instructions.last().map(|&(address, _)| address))
+            .collect()
+    }
+
+    pub fn functions(&self) -> Vec<Function> {
+        let mut entries = self
+            .blocks
+            .values()
+            .filter_map(|block| block.call)
+            .collect::<BTreeSet<_>>();
+        entries.insert(self.entry);
+        entries
+            .into_iter()
+            .filter(|entry| self.blocks.contains_key(entry))
+            .map(|entry| {
+                let mut blocks = BTreeSet::new();
+                let mut pending = vec![entry];
+                while let Some(start) = pending.pop() {
+                    if blocks.insert(start) {
+                        pending.extend(self.intraprocedural_successors(&self.blocks[&start]));
+                    }
+                }
+                let calls = blocks
+                    .iter()
+                    .filter_map(|start| self.blocks[start].call)
+                    .collect();
+                Function {
+                    entry,
+                    blocks,
+                    calls,
+                }
+            })
+            .collect()
+    }
+
+    // Blocks dominating each block reachable from a function entry, through
+    // the intraprocedural edges.
+    fn dominators(&self) -> BTreeMap<usize, BTreeSet<usize>> {
+        let entries = self
+            .functions()
+            .into_iter()
+            .map(|function| function.entry)
+            .collect::<BTreeSet<_>>();
+        let mut predecessors = BTreeMap::<usize, Vec<usize>>::new();
+        let mut reachable = entries.clone();
+        let mut pending = entries.iter().copied().collect::<Vec<_>>();
+        while let Some(start) = pending.pop() {
+            for successor in self.intraprocedural_successors(&self.blocks[&start]) {
+                predecessors.entry(successor).or_default().push(start);
+                if reachable.insert(successor) {
+                    pending.push(successor);
+                }
+            }
+        }
+        let mut dominators = reachable
+            .iter()
+            .map(|&start| {
+                let dominators = if entries.contains(&start) {
+                    Some(start).into_iter().collect()
+                } else {
+                    reachable.clone()
+                };
+                (start, dominators)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &start in reachable.difference(&entries) {
+                let mut updated = predecessors[&start]
+                    .iter()
+                    .map(|predecessor| &dominators[predecessor])
+                    .fold(None, |common: Option<BTreeSet<usize>>, next| match common {
+                        Some(common) => Some(common.intersection(next).copied().collect()),
+                        None => Some(next.clone()),
+                    })
+                    .unwrap_or_default();
+                updated.insert(start);
+                if updated != dominators[&start] {
+                    dominators.insert(start, updated);
+                    changed = true;
+                }
+            }
+        }
+        dominators
+    }
+
+    // Natural loops: a back edge goes to a header that dominates its source,
+    // and the loop is every block reaching the source without the header.
+    pub fn loops(&self) -> Vec<Loop> {
+        let dominators = self.dominators();
+        let mut predecessors = BTreeMap::<usize, Vec<usize>>::new();
+        let mut back_edges = Vec::new();
+        for (&start, dominated_by) in &dominators {
+            for successor in self.intraprocedural_successors(&self.blocks[&start]) {
+                predecessors.entry(successor).or_default().push(start);
+                if dominated_by.contains(&successor) {
+                    back_edges.push((start, successor));
+                }
+            }
+        }
+
+        let mut loops = BTreeMap::<usize, BTreeSet<usize>>::new();
+        for (latch, header) in back_edges {
+            let blocks = loops.entry(header).or_default();
+            blocks.insert(header);
+            let mut pending = vec![latch];
+            while let Some(start) = pending.pop() {
+                if blocks.insert(start) {
+                    pending.extend(predecessors.get(&start).into_iter().flatten());
+                }
+            }
+        }
+        loops
+            .into_iter()
+            .map(|(header, blocks)| Loop { header, blocks })
+            .collect()
+    }
+
+    pub fn dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph intcode {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=monospace];").unwrap();
+        let mut unknown = false;
+        for block in self.blocks.values() {
+            let label = block
+                .instructions
+                .iter()
+                .map(|&(address, _)| {
+                    let source = disassemble_instruction(&block.words, address - block.start)
+                        .unwrap_or_default();
+                    format!("{}: {}\\l", address, source)
+                })
+                .collect::<String>();
+            let label = match self.labels.get(&block.start) {
+                Some(name) => format!("{}:\\l{}", name, label),
+                None => label,
+            };
+            writeln!(dot, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+            for successor in &block.successors {
+                match (successor, block.call) {
+                    (Successor::Block(target), Some(_)) => {
+                        writeln!(dot, "    b{} -> b{} [style=bold];", block.start, target)
+                    }
+                    (Successor::Block(target), None) => {
+                        writeln!(dot, "    b{} -> b{};", block.start, target)
+                    }
+                    (Successor::Unknown, _) => {
+                        unknown = true;
+                        writeln!(dot, "    b{} -> unknown [style=dashed];", block.start)
+                    }
+                }
+                .unwrap();
+            }
+            if block.call.is_some() && self.blocks.contains_key(&block.end) {
+                writeln!(
+                    dot,
+                    "    b{} -> b{} [style=dotted];",
+                    block.start, block.end
+                )
+                .unwrap();
+            }
+        }
+        if unknown {
+            writeln!(dot, "    unknown [shape=ellipse, label=\"?\"];").unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    pub fn summary(&self) -> String {
+        let list = |blocks: &BTreeSet<usize>| {
+            blocks
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut summary = String::new();
+        let functions = self.functions();
+        writeln!(
+            summary,
+            "{} blocks, {} functions",
+            self.blocks.len(),
+            functions.len()
+        )
+        .unwrap();
+        for function in &functions {
+            write!(
+                summary,
+                "function {}: {} blocks ({})",
+                self.name(function.entry),
+                function.blocks.len(),
+                list(&function.blocks)
+            )
+            .unwrap();
+            if function.calls.contains(&function.entry) {
+                write!(summary, ", recursive").unwrap();
+            }
+            if !function.calls.is_empty() {
+                write!(summary, ", calls {}", list(&function.calls)).unwrap();
+            }
+            writeln!(summary).unwrap();
+        }
+        for program_loop in self.loops() {
+            writeln!(
+                summary,
+                "loop {}: {} blocks ({})",
+                self.name(program_loop.header),
+                program_loop.blocks.len(),
+                list(&program_loop.blocks)
+            )
+            .unwrap();
+        }
+        let indirect = self.indirect_jumps();
+        if !indirect.is_empty() {
+            let indirect = indirect.into_iter().collect::<BTreeSet<_>>();
+            writeln!(summary, "indirect jumps at {}", list(&indirect)).unwrap();
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+const CALL_AND_LOOP: &[i128] = &[
+    1101, 7, 0, 30, 1106, 0, 10, 4, 31, 99, 1001, 31, 1, 31, 1007, 31, 5, 32, 1005, 32, 10, 106, 0,
+    30, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+#[test]
+fn test_basic_blocks() {
+    let graph = ControlFlowGraph::new(CALL_AND_LOOP);
+    assert_eq!(
+        graph.blocks.keys().copied().collect::<Vec<_>>(),
+        vec![0, 7, 10, 21]
+    );
+    let block = &graph.blocks[&0];
+    assert_eq!((block.end, block.call), (7, Some(10)));
+    assert_eq!(block.successors, vec![Successor::Block(10)]);
+    assert_eq!(graph.blocks[&7].successors, vec![]);
+    assert_eq!(
+        graph.blocks[&10].successors,
+        vec![Successor::Block(10), Successor::Block(21)]
+    );
+    assert_eq!(graph.blocks[&21].successors, vec![Successor::Unknown]);
+    assert_eq!(graph.indirect_jumps(), vec![21]);
+}
+
+#[test]
+fn test_functions_and_loops() {
+    let graph = ControlFlowGraph::new(CALL_AND_LOOP);
+    let set = |blocks: &[usize]| blocks.iter().copied().collect::<BTreeSet<_>>();
+    assert_eq!(
+        graph.functions(),
+        vec![
+            Function {
+                entry: 0,
+                blocks: set(&[0, 7]),
+                calls: set(&[10]),
+            },
+            Function {
+                entry: 10,
+                blocks: set(&[10, 21]),
+                calls: set(&[]),
+            },
+        ]
+    );
+    assert_eq!(
+        graph.loops(),
+        vec![Loop {
+            header: 10,
+            blocks: set(&[10]),
+        }]
+    );
+    assert!(graph.summary().contains("loop 10: 1 blocks (10)"));
+    let dot = graph.dot();
+    assert!(dot.contains("b0 -> b10 [style=bold];"));
+    assert!(dot.contains("b21 -> unknown [style=dashed];"));
+}
+
+#[test]
+fn test_image_entry_and_symbols() {
+    let image = "#!entry 3\n#!symbol main 3\n42,0,0,104,7,99"
+        .parse::<Image>()
+        .unwrap();
+    let graph = ControlFlowGraph::from_image(&image);
+    assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), vec![3]);
+    assert!(graph.summary().contains("function 3 (main): 1 blocks (3)"));
+    assert!(graph
+        .dot()
+        .contains("b3 [label=\"main:\\l3: out #7\\l5: hlt\\l\"];"));
+    assert!(ControlFlowGraph::new(&image.intcode).blocks.is_empty());
+}
+
+#[test]
+fn test_irreducible_cycle() {
+    // 6 and 14 jump to each other, but either can be entered first
+    let intcode = [
+        1005, 18, 6, 1106, 0, 14, 1, 18, 18, 18, 1005, 18, 14, 99, 1005, 18, 6, 99, 0,
+    ];
+    let graph = ControlFlowGraph::new(&intcode);
+    assert_eq!(
+        graph.blocks.keys().copied().collect::<Vec<_>>(),
+        vec![0, 3, 6, 13, 14, 17]
+    );
+    assert_eq!(
+        graph.blocks[&14].successors,
+        vec![Successor::Block(6), Successor::Block(17)]
+    );
+    assert_eq!(graph.loops(), vec![]);
+
+    // entering only through 6 makes it a loop
+    let mut intcode = intcode;
+    intcode[2] = 3;
+    intcode[5] = 6;
+    let set = |blocks: &[usize]| blocks.iter().copied().collect::<BTreeSet<_>>();
+    assert_eq!(
+        ControlFlowGraph::new(&intcode).loops(),
+        vec![Loop {
+            header: 6,
+            blocks: set(&[6, 14]),
+        }]
+    );
+}
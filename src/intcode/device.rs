@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+pub trait InputDevice<W = i128> {
+    fn read(&mut self) -> Option<W>;
+}
+
+pub trait OutputDevice<W = i128> {
+    fn write(&mut self, value: W);
+}
+
+impl<W, D: InputDevice<W> + ?Sized> InputDevice<W> for &mut D {
+    fn read(&mut self) -> Option<W> {
+        (**self).read()
+    }
+}
+
+impl<W, D: OutputDevice<W> + ?Sized> OutputDevice<W> for &mut D {
+    fn write(&mut self, value: W) {
+        (**self).write(value)
+    }
+}
+
+impl<W> InputDevice<W> for VecDeque<W> {
+    fn read(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+}
+
+impl<W> OutputDevice<W> for VecDeque<W> {
+    fn write(&mut self, value: W) {
+        self.push_back(value)
+    }
+}
+
+impl<W> OutputDevice<W> for Vec<W> {
+    fn write(&mut self, value: W) {
+        self.push(value)
+    }
+}
+
+impl<W> InputDevice<W> for Receiver<W> {
+    fn read(&mut self) -> Option<W> {
+        self.recv().ok()
+    }
+}
+
+impl<W> OutputDevice<W> for Sender<W> {
+    fn write(&mut self, value: W) {
+        // the receiving end may already have stopped listening, e.g. a halted VM
+        let _ = self.send(value);
+    }
+}
+
+pub struct InputFn<F>(pub F);
+
+impl<W, F: FnMut() -> Option<W>> InputDevice<W> for InputFn<F> {
+    fn read(&mut self) -> Option<W> {
+        (self.0)()
+    }
+}
+
+pub struct OutputFn<F>(pub F);
+
+impl<W, F: FnMut(W)> OutputDevice<W> for OutputFn<F> {
+    fn write(&mut self, value: W) {
+        (self.0)(value)
+    }
+}
+
+pub struct LineReader<R> {
+    reader: R,
+    prompt: String,
+}
+
+impl LineReader<std::io::StdinLock<'static>> {
+    pub fn stdin(prompt: &str) -> Self {
+        Self::new(std::io::stdin().lock(), prompt)
+    }
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R, prompt: &str) -> Self {
+        Self {
+            reader,
+            prompt: prompt.to_string(),
+        }
+    }
+}
+
+impl<R: BufRead> InputDevice for LineReader<R> {
+    fn read(&mut self) -> Option<i128> {
+        loop {
+            if !self.prompt.is_empty() {
+                print!("{}", self.prompt);
+                std::io::stdout().flush().ok()?;
+            }
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            match line.trim().parse() {
+                Ok(value) => return Some(value),
+                Err(_) => eprintln!("not a number: `{}`", line.trim()),
+            }
+        }
+    }
+}
+
+pub struct LineWriter<W>(pub W);
+
+impl LineWriter<std::io::Stdout> {
+    pub fn stdout() -> Self {
+        LineWriter(std::io::stdout())
+    }
+}
+
+impl<W: Write> OutputDevice for LineWriter<W> {
+    fn write(&mut self, value: i128) {
+        writeln!(self.0, "{}", value).expect("failed to write output");
+    }
+}
+
+// Line-buffered ASCII text: each line becomes its character codes and a
+// trailing newline. Script lines are played first, and written to the echo
+// writer as if typed.
+pub struct AsciiReader<R> {
+    reader: R,
+    script: VecDeque<String>,
+    pending: VecDeque<i128>,
+    echo: Option<Box<dyn Write>>,
+}
+
+impl AsciiReader<std::io::StdinLock<'static>> {
+    pub fn stdin() -> Self {
+        Self::new(std::io::stdin().lock())
+    }
+}
+
+impl<R: BufRead> AsciiReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            script: VecDeque::new(),
+            pending: VecDeque::new(),
+            echo: None,
+        }
+    }
+
+    pub fn with_script(mut self, script: &str) -> Self {
+        self.script.extend(script.lines().map(str::to_string));
+        self
+    }
+
+    // Also flushed before waiting for a line, so that a prompt written
+    // through the same stream shows up.
+    pub fn with_echo(mut self, echo: impl Write + 'static) -> Self {
+        self.echo = Some(Box::new(echo));
+        self
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        if let Some(line) = self.script.pop_front() {
+            if let Some(echo) = &mut self.echo {
+                writeln!(echo, "{}", line).ok()?;
+            }
+            return Some(line);
+        }
+        if let Some(echo) = &mut self.echo {
+            echo.flush().ok()?;
+        }
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        Some(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+    }
+}
+
+impl<R: BufRead> InputDevice for AsciiReader<R> {
+    fn read(&mut self) -> Option<i128> {
+        if self.pending.is_empty() {
+            let line = self.next_line()?;
+            self.pending.extend(line.bytes().map(i128::from));
+            self.pending.push_back(10);
+        }
+        self.pending.pop_front()
+    }
+}
+
+// Renders ASCII output as text and anything else as a number on its own line,
+// where it fell in the text.
+pub struct AsciiWriter<W>(pub W);
+
+impl AsciiWriter<std::io::Stdout> {
+    pub fn stdout() -> Self {
+        AsciiWriter(std::io::stdout())
+    }
+}
+
+impl<W: Write> OutputDevice for AsciiWriter<W> {
+    fn write(&mut self, value: i128) {
+        match value {
+            10 => writeln!(self.0).and_then(|_| self.0.flush()),
+            0..=127 => self.0.write_all(&[value as u8]),
+            _ => writeln!(self.0, "{}", value),
+        }
+        .expect("failed to write output");
+    }
+}
+
+pub struct Recorder<D, W = i128> {
+    pub device: D,
+    pub values: Vec<W>,
+}
+
+impl<D, W> Recorder<D, W> {
+    pub fn new(device: D) -> Self {
+        Self {
+            device,
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<W: Clone, D: InputDevice<W>> InputDevice<W> for Recorder<D, W> {
+    fn read(&mut self) -> Option<W> {
+        let value = self.device.read()?;
+        self.values.push(value.clone());
+        Some(value)
+    }
+}
+
+impl<W: Clone, D: OutputDevice<W>> OutputDevice<W> for Recorder<D, W> {
+    fn write(&mut self, value: W) {
+        self.values.push(value.clone());
+        self.device.write(value)
+    }
+}
+
+#[test]
+fn test_line_reader() {
+    let mut reader = LineReader::new("12\nfoo\n-3\n".as_bytes(), "");
+    assert_eq!(reader.read(), Some(12));
+    assert_eq!(reader.read(), Some(-3));
+    assert_eq!(reader.read(), None);
+}
+
+#[test]
+fn test_ascii_console() {
+    use crate::Program;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Echo(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Echo {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write_all(bytes)?;
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let echo = Rc::new(RefCell::new(Vec::new()));
+    let mut input = AsciiReader::new("look\r\n".as_bytes())
+        .with_script("go north\n")
+        .with_echo(Echo(echo.clone()));
+    let mut codes = std::iter::from_fn(|| input.read()).collect::<Vec<_>>();
+    assert_eq!(&echo.borrow()[..], b"go north\n");
+    assert_eq!(
+        codes.split_off(9),
+        b"look\n".iter().map(|&c| c as i128).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        codes,
+        b"go north\n".iter().map(|&c| c as i128).collect::<Vec<_>>()
+    );
+
+    let mut output = AsciiWriter(Vec::new());
+    let intcode = [104, 104, 104, 105, 104, 10, 104, 1_000, 104, 33, 99];
+    Program::new(&intcode, &[])
+        .run_with(&mut VecDeque::new(), &mut output)
+        .unwrap();
+    assert_eq!(String::from_utf8(output.0).unwrap(), "hi\n1000\n!");
+
+    let mut output = AsciiWriter(Vec::new());
+    for &value in &[111, 107, 10, 1_000, 10] {
+        output.write(value);
+    }
+    assert_eq!(String::from_utf8(output.0).unwrap(), "ok\n1000\n\n");
+}
+
+#[test]
+fn test_channel_connects_programs() {
+    use crate::Program;
+    use std::sync::mpsc::channel;
+
+    let double = [3, 9, 1002, 9, 2, 9, 4, 9, 99, 0];
+    let (mut sender, mut receiver) = channel();
+    let first = std::thread::spawn(move || {
+        Program::new(&double, &[]).run_with(&mut VecDeque::from(vec![21]), &mut sender)
+    });
+    let mut output = Recorder::new(Vec::new());
+    Program::new(&double, &[])
+        .run_with(&mut receiver, &mut output)
+        .unwrap();
+    first.join().unwrap().unwrap();
+    assert_eq!(output.values, vec![84]);
+    assert_eq!(output.device, vec![84]);
+}
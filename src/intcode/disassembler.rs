@@ -0,0 +1,355 @@
+use crate::image::Image;
+use crate::opcode::{Opcode, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+const DATA_WORDS_PER_LINE: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LineKind {
+    Instruction(Opcode),
+    Data,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub address: usize,
+    pub words: Vec<i128>,
+    pub kind: LineKind,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<usize, String>,
+}
+
+pub fn decode(intcode: &[i128], address: usize) -> Option<Opcode> {
+    let word = *intcode.get(address)?;
+    let opcode = Opcode::new(address, word).ok()?;
+    let count = opcode.parameter_count()?;
+    let modes = (0..count)
+        .map(|index| opcode.parameters.mode(index))
+        .collect::<Vec<_>>();
+    let writes_immediate = opcode
+        .written_parameter()
+        .is_some_and(|index| modes[index] == ParameterMode::Immediate);
+    if address + count < intcode.len()
+        && !writes_immediate
+        && Opcode::encode(opcode.code, &modes) == word
+    {
+        Some(opcode)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn jump_target(opcode: &Opcode, operands: &[i128]) -> Option<usize> {
+    match opcode.code {
+        5 | 6 if opcode.parameters.mode(1) == ParameterMode::Immediate => {
+            usize::try_from(operands[1]).ok()
+        }
+        _ => None,
+    }
+}
+
+fn constant_condition(opcode: &Opcode, operands: &[i128]) -> Option<bool> {
+    if opcode.parameters.mode(0) == ParameterMode::Immediate {
+        Some(operands[0] != 0)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn falls_through(opcode: &Opcode, operands: &[i128]) -> bool {
+    !matches!(
+        (opcode.code, constant_condition(opcode, operands)),
+        (99, _) | (5, Some(true)) | (6, Some(false))
+    )
+}
+
+// Whether a jump can be taken, as opposed to one on a constant condition
+// that never holds.
+pub(crate) fn can_jump(opcode: &Opcode, operands: &[i128]) -> bool {
+    matches!(
+        (opcode.code, constant_condition(opcode, operands)),
+        (5, Some(true)) | (6, Some(false)) | (5 | 6, None)
+    )
+}
+
+pub fn reachable(intcode: &[i128], entry_points: &[usize]) -> BTreeSet<usize> {
+    let mut code = BTreeSet::new();
+    let mut pending = entry_points.to_vec();
+    while let Some(address) = pending.pop() {
+        if code.contains(&address) {
+            continue;
+        }
+        let opcode = match decode(intcode, address) {
+            Some(opcode) => opcode,
+            None => continue,
+        };
+        code.insert(address);
+        let size = opcode.parameter_count().unwrap_or_default() + 1;
+        let operands = &intcode[address + 1..address + size];
+        pending.extend(jump_target(&opcode, operands));
+        if falls_through(&opcode, operands) {
+            pending.push(address + size);
+        }
+    }
+    code
+}
+
+pub fn disassemble_instruction(intcode: &[i128], address: usize) -> Option<String> {
+    let opcode = decode(intcode, address)?;
+    let size = opcode.parameter_count().unwrap_or_default() + 1;
+    let line = Line {
+        address,
+        words: intcode[address..address + size].to_vec(),
+        kind: LineKind::Instruction(opcode),
+    };
+    Some(Disassembly::default().source(&line))
+}
+
+pub fn disassemble(intcode: &[i128]) -> Disassembly {
+    disassemble_from(intcode, &[0])
+}
+
+pub fn disassemble_from(intcode: &[i128], entry_points: &[usize]) -> Disassembly {
+    disassemble_with(intcode, entry_points, &BTreeMap::new())
+}
+
+// Starts from the image's entry point and uses its symbols in place of the
+// generated labels.
+pub fn disassemble_image(image: &Image) -> Disassembly {
+    disassemble_with(&image.intcode, &[image.entry], &image.symbols)
+}
+
+fn disassemble_with(
+    intcode: &[i128],
+    entry_points: &[usize],
+    symbols: &BTreeMap<usize, String>,
+) -> Disassembly {
+    let code = reachable(intcode, entry_points);
+    let mut jump_targets = BTreeSet::new();
+    let mut data_references = BTreeSet::new();
+    for &address in &code {
+        let opcode = decode(intcode, address).unwrap();
+        let size = opcode.parameter_count().unwrap_or_default() + 1;
+        let operands = &intcode[address + 1..address + size];
+        jump_targets.extend(jump_target(&opcode, operands));
+        for (index, &operand) in operands.iter().enumerate() {
+            if opcode.parameters.mode(index) == ParameterMode::Position {
+                data_references.extend(usize::try_from(operand).ok());
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut address = 0;
+    while address < intcode.len() {
+        if code.contains(&address) {
+            let opcode = decode(intcode, address).unwrap();
+            let size = opcode.parameter_count().unwrap_or_default() + 1;
+            lines.push(Line {
+                address,
+                words: intcode[address..address + size].to_vec(),
+                kind: LineKind::Instruction(opcode),
+            });
+            address += size;
+        } else {
+            let mut end = address + 1;
+            while end < intcode.len()
+                && end - address < DATA_WORDS_PER_LINE
+                && !code.contains(&end)
+                && !jump_targets.contains(&end)
+                && !data_references.contains(&end)
+                && !symbols.contains_key(&end)
+            {
+                end += 1;
+            }
+            lines.push(Line {
+                address,
+                words: intcode[address..end].to_vec(),
+                kind: LineKind::Data,
+            });
+            address = end;
+        }
+    }
+
+    let line_starts = lines
+        .iter()
+        .map(|line| line.address)
+        .collect::<BTreeSet<_>>();
+    // generated names step around the symbols they would clash with
+    let generated = |prefix: &str, address: usize| {
+        let name = format!("{}{}", prefix, address);
+        std::iter::once(name.clone())
+            .chain((1..).map(|suffix| format!("{}_{}", name, suffix)))
+            .find(|name| !symbols.values().any(|symbol| symbol == name))
+            .unwrap()
+    };
+    let mut labels = BTreeMap::new();
+    for &address in data_references.intersection(&line_starts) {
+        labels.insert(address, generated("d", address));
+    }
+    for &address in jump_targets.intersection(&line_starts) {
+        labels.insert(address, generated("l", address));
+    }
+    for (address, name) in symbols {
+        if line_starts.contains(address) {
+            labels.insert(*address, name.clone());
+        }
+    }
+    Disassembly { lines, labels }
+}
+
+impl Disassembly {
+    fn operand(&self, opcode: &Opcode, index: usize, value: i128) -> String {
+        let label = usize::try_from(value)
+            .ok()
+            .and_then(|address| self.labels.get(&address));
+        match (opcode.parameters.mode(index), label) {
+            (ParameterMode::Position, Some(label)) => format!("[{}]", label),
+            (ParameterMode::Position, None) => format!("[{}]", value),
+            (ParameterMode::Immediate, Some(label))
+                if index == 1 && jump_target(opcode, &[0, value]).is_some() =>
+            {
+                format!("#{}", label)
+            }
+            (ParameterMode::Immediate, _) => format!("#{}", value),
+            (ParameterMode::Relative, _) if value == 0 => "rb".to_string(),
+            (ParameterMode::Relative, _) if value < 0 => format!("rb{}", value),
+            (ParameterMode::Relative, _) => format!("rb+{}", value),
+        }
+    }
+
+    pub(crate) fn source(&self, line: &Line) -> String {
+        match &line.kind {
+            LineKind::Instruction(opcode) => {
+                let operands = line.words[1..]
+                    .iter()
+                    .enumerate()
+                    .map(|(index, &value)| self.operand(opcode, index, value))
+                    .collect::<Vec<_>>();
+                let mnemonic = opcode.mnemonic().unwrap_or_default();
+                if operands.is_empty() {
+                    mnemonic.to_string()
+                } else {
+                    format!("{} {}", mnemonic, operands.join(", "))
+                }
+            }
+            LineKind::Data => format!(
+                "db {}",
+                line.words
+                    .iter()
+                    .map(i128::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            let label = self
+                .labels
+                .get(&line.address)
+                .map(|label| format!("{}:", label))
+                .unwrap_or_default();
+            let words = line
+                .words
+                .iter()
+                .map(i128::to_string)
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(
+                f,
+                "{:<8}{:<40}; {:>5}: {}",
+                label,
+                self.source(line),
+                line.address,
+                words
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_disassemble_separates_code_and_data() {
+    let intcode = [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+    let disassembly = disassemble(&intcode);
+    let kinds = disassembly
+        .lines
+        .iter()
+        .map(|line| (line.address, matches!(line.kind, LineKind::Instruction(_))))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            (0, true),
+            (2, true),
+            (5, true),
+            (9, true),
+            (11, true),
+            (12, false),
+            (13, false),
+            (14, false),
+            (15, false),
+        ]
+    );
+    assert_eq!(disassembly.labels.get(&12), Some(&"d12".to_string()));
+}
+
+#[test]
+fn test_disassemble_follows_jumps() {
+    let intcode = [1105, 1, 4, 42, 104, 7, 99];
+    let disassembly = disassemble(&intcode);
+    assert_eq!(disassembly.labels.get(&4), Some(&"l4".to_string()));
+    assert_eq!(disassembly.lines[1].kind, LineKind::Data);
+    assert_eq!(disassembly.lines[1].words, vec![42]);
+    let text = disassembly.to_string();
+    assert!(text.starts_with("        jt #1, #l4"));
+    assert!(text.contains("l4:     out #7"));
+}
+
+#[test]
+fn test_disassemble_image() {
+    let image = "#!entry 3\n#!symbol main 3\n#!symbol value 1\n42,0,0,104,7,99"
+        .parse::<Image>()
+        .unwrap();
+    let text = disassemble_image(&image).to_string();
+    assert!(text.starts_with("        db 42 "));
+    assert!(text.contains("value:  db 0, 0 "));
+    assert!(text.contains("main:   out #7 "));
+    assert_eq!(&crate::assemble(&text).unwrap(), &image.intcode);
+
+    // symbols named like generated labels
+    let image = "#!symbol d5 0\n#!symbol d5_1 1\n1101,1,1,5,99,0"
+        .parse::<Image>()
+        .unwrap();
+    let text = disassemble_image(&image).to_string();
+    assert!(text.contains("d5_2:   db 0"));
+    assert_eq!(&crate::assemble(&text).unwrap(), &image.intcode);
+}
+
+#[test]
+fn test_disassemble_round_trip() {
+    for intcode in &[
+        vec![
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ],
+        vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ],
+        vec![1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0],
+    ] {
+        let source = disassemble(intcode).to_string();
+        assert_eq!(&crate::assemble(&source).unwrap(), intcode);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmError {
+    InvalidOpcode { ip: usize, opcode: i128 },
+    BadParameterMode { ip: usize, opcode: i128 },
+    WriteToImmediate { ip: usize },
+    NegativeAddress { ip: usize, address: i128 },
+    InputStarvation { ip: usize },
+    RanOffEnd { ip: usize },
+    BudgetExhausted { ip: usize },
+    MemoryLimit { ip: usize, address: usize },
+    Overflow { ip: usize },
+}
+
+impl VmError {
+    pub fn ip(&self) -> usize {
+        match *self {
+            VmError::InvalidOpcode { ip, .. }
+            | VmError::BadParameterMode { ip, .. }
+            | VmError::WriteToImmediate { ip }
+            | VmError::NegativeAddress { ip, .. }
+            | VmError::InputStarvation { ip }
+            | VmError::RanOffEnd { ip }
+            | VmError::BudgetExhausted { ip }
+            | VmError::MemoryLimit { ip, .. }
+            | VmError::Overflow { ip } => ip,
+        }
+    }
+
+    // The message with the faulting location written as `at`, which is
+    // `ip=N` for `Display`.
+    pub fn message(&self, at: &str) -> String {
+        match self {
+            VmError::InvalidOpcode { opcode, .. } => {
+                format!("invalid opcode `{}` at {}", opcode, at)
+            }
+            VmError::BadParameterMode { opcode, .. } => {
+                format!("unknown parameter mode in `{}` at {}", opcode, at)
+            }
+            VmError::WriteToImmediate { .. } => {
+                format!("output cannot be in immediate mode at {}", at)
+            }
+            VmError::NegativeAddress { address, .. } => {
+                format!("negative address `{}` at {}", address, at)
+            }
+            VmError::InputStarvation { .. } => format!("no input at {}", at),
+            VmError::RanOffEnd { .. } => format!("no end found at {}", at),
+            VmError::BudgetExhausted { .. } => format!("budget exhausted at {}", at),
+            VmError::MemoryLimit { address, .. } => format!(
+                "memory limit exceeded writing address {} at {}",
+                address, at
+            ),
+            VmError::Overflow { .. } => format!("arithmetic overflow at {}", at),
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message(&format!("ip={}", self.ip())))
+    }
+}
+
+impl std::error::Error for VmError {}
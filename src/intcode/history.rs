@@ -0,0 +1,169 @@
+use crate::memory::Memory;
+use crate::opcode::Opcode;
+use crate::program::Program;
+use crate::word::Word;
+use std::collections::VecDeque;
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 1 << 20;
+
+// What it takes to undo one executed instruction: the registers before it,
+// the previous value of the cell it wrote and the input it consumed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Undo<W = i128> {
+    pub instructions: u64,
+    pub ip: usize,
+    pub relative_base: i128,
+    pub opcode: Opcode,
+    pub write: Option<(usize, W)>,
+    pub input: Option<W>,
+}
+
+// Undo records of the most recent instructions, oldest dropped first once
+// `limit` is reached.
+#[derive(Clone, Debug, PartialEq)]
+pub struct History<W = i128> {
+    pub undo: VecDeque<Undo<W>>,
+    pub limit: usize,
+}
+
+impl<W: Word> Undo<W> {
+    pub fn new(
+        opcode: &Opcode,
+        intcode: &Memory<W>,
+        next_inputs: &VecDeque<W>,
+        instructions: u64,
+        ip: usize,
+        relative_base: i128,
+    ) -> Self {
+        let write = opcode.written_parameter().and_then(|index| {
+            let address = opcode
+                .parameters
+                .peek_address(index, intcode, ip, relative_base)?;
+            Some((address, intcode.get(address)))
+        });
+        let input = if opcode.code == 3 {
+            next_inputs.front().cloned()
+        } else {
+            None
+        };
+        Undo {
+            instructions,
+            ip,
+            relative_base,
+            opcode: *opcode,
+            write,
+            input,
+        }
+    }
+}
+
+impl<W> History<W> {
+    pub fn new(limit: usize) -> Self {
+        History {
+            undo: VecDeque::new(),
+            limit,
+        }
+    }
+
+    pub fn push(&mut self, undo: Undo<W>) {
+        if self.undo.len() >= self.limit {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(undo);
+    }
+
+    pub fn last_write(&self, address: usize) -> Option<&Undo<W>> {
+        self.undo
+            .iter()
+            .rev()
+            .find(|undo| undo.write.as_ref().is_some_and(|write| write.0 == address))
+    }
+}
+
+impl<W> Default for History<W> {
+    fn default() -> Self {
+        History::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl<W: Word> Program<W> {
+    pub fn with_history(mut self, limit: usize) -> Self {
+        self.history = Some(History::new(limit));
+        self
+    }
+
+    // Undoes the last recorded instruction and returns its record, or None at
+    // the start of the history.
+    pub fn reverse_step(&mut self) -> Option<Undo<W>> {
+        let undo = self.history.as_mut()?.undo.pop_back()?;
+        self.instructions = undo.instructions;
+        self.halted = false;
+        self.ip = undo.ip;
+        self.relative_base = undo.relative_base;
+        if let Some((address, value)) = &undo.write {
+            if let Some(cell) = self.intcode.get_mut(*address) {
+                *cell = value.clone();
+            }
+        }
+        if let Some(value) = &undo.input {
+            self.next_inputs.push_front(value.clone());
+        }
+        Some(undo)
+    }
+
+    // Steps back until just before the last write to `address`.
+    pub fn reverse_to_write(&mut self, address: usize) -> Option<Undo<W>> {
+        self.history.as_ref()?.last_write(address)?;
+        loop {
+            let undo = self.reverse_step()?;
+            if undo.write.as_ref().is_some_and(|write| write.0 == address) {
+                return Some(undo);
+            }
+        }
+    }
+
+    pub fn last_write(&self, address: usize) -> Option<&Undo<W>> {
+        self.history.as_ref()?.last_write(address)
+    }
+}
+
+#[test]
+fn test_reverse_step() {
+    // reads a value, squares it into [11] and outputs it
+    let intcode = [3, 11, 2, 11, 11, 11, 4, 11, 99, 0, 0, 0];
+    let mut program = Program::new(&intcode, &[7]).with_history(16);
+    assert_eq!(program.next_output(&[]), Ok(Some(49)));
+    assert_eq!(program.ip, 8);
+    let writer = program.last_write(11).unwrap();
+    assert_eq!((writer.ip, writer.instructions), (2, 1));
+    assert_eq!(writer.write, Some((11, 7)));
+
+    assert_eq!(program.reverse_step().map(|undo| undo.opcode.code), Some(4));
+    let undo = program.reverse_step().unwrap();
+    assert_eq!((undo.ip, program.ip, program.intcode[11]), (2, 2, 7));
+    assert_eq!(program.instructions, 1);
+    let undo = program.reverse_step().unwrap();
+    assert_eq!(undo.input, Some(7));
+    assert_eq!((program.ip, program.intcode[11]), (0, 0));
+    assert_eq!(program.next_inputs, VecDeque::from(vec![7]));
+    assert_eq!(program.reverse_step(), None);
+    assert_eq!(program.next_output(&[]), Ok(Some(49)));
+}
+
+#[test]
+fn test_reverse_to_write() {
+    // counts [14] down from 3, then outputs it
+    let intcode = [1101, 3, 0, 14, 1001, 14, -1, 14, 1005, 14, 4, 4, 14, 99, 0];
+    let mut program = Program::new(&intcode, &[]).with_history(100);
+    assert_eq!(program.run(&mut VecDeque::new()), Ok(vec![0]));
+    let undo = program.reverse_to_write(14).unwrap();
+    assert_eq!(undo.write, Some((14, 1)));
+    assert_eq!((program.ip, program.intcode[14]), (4, 1));
+    assert_eq!(program.instructions, 5);
+
+    let mut program = Program::new(&intcode, &[]).with_history(2);
+    program.run(&mut VecDeque::new()).unwrap();
+    assert_eq!(program.history.as_ref().unwrap().undo.len(), 2);
+    assert_eq!(program.reverse_to_write(14), None);
+    assert_eq!(program.ip, 13);
+}
@@ -0,0 +1,533 @@
+use crate::assembler::{assemble_with_symbols, is_identifier, AsmError};
+use crate::disassembler::decode;
+use crate::program::Program;
+use crate::symbols::SourceMap;
+use crate::transpile::size;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+const MAGIC: &[u8; 4] = b"ICB\0";
+const VERSION: u8 = 2;
+const WORDS_PER_LINE: usize = 16;
+
+// A program together with the metadata both file formats can carry.
+//
+// The text format is the usual comma separated words; whitespace, newlines,
+// a trailing comma and `#` comments are allowed, and `#!` lines hold the
+// metadata:
+//
+//     #!width 64
+//     #!entry 4
+//     #!symbol loop_start 12
+//     #!line 7 12
+//
+// where a line directive gives the source line of the instruction or data at
+// an address, as kept from assembly.
+//
+// The binary format is the magic `ICB\0`, a version byte, the word width in
+// bits as a byte, then the entry point, the symbol count, each symbol as
+// name length, name and address, the line count, each line as address and
+// line, the word count and the zigzag encoded words, all as LEB128 varints.
+// Version 1 files have no lines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub intcode: Vec<i128>,
+    pub width: u32,
+    pub entry: usize,
+    pub symbols: BTreeMap<usize, String>,
+    pub lines: BTreeMap<usize, usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseImageError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid program at offset {}: {}",
+            self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseImageError {}
+
+fn error<T>(offset: usize, message: impl Into<String>) -> Result<T, ParseImageError> {
+    Err(ParseImageError {
+        offset,
+        message: message.into(),
+    })
+}
+
+fn fits(word: i128, width: u32) -> bool {
+    width >= 128 || (word >= -(1 << (width - 1)) && word < 1 << (width - 1))
+}
+
+fn zigzag(word: i128) -> u128 {
+    ((word << 1) ^ (word >> 127)) as u128
+}
+
+fn unzigzag(value: u128) -> i128 {
+    ((value >> 1) as i128) ^ -((value & 1) as i128)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u128) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, ParseImageError> {
+        let byte = match self.bytes.get(self.offset) {
+            Some(&byte) => byte,
+            None => return error(self.offset, "unexpected end of file"),
+        };
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u128, ParseImageError> {
+        let start = self.offset;
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            let bits = u128::from(byte & 0x7f);
+            if shift == 126 && bits > 0b11 {
+                return error(start, "varint overflows 128 bits");
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 126 {
+                return error(start, "varint overflows 128 bits");
+            }
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize, ParseImageError> {
+        let start = self.offset;
+        let value = self.varint()?;
+        usize::try_from(value).or_else(|_| error(start, format!("{} is out of range", value)))
+    }
+}
+
+// Each address has at most one name and each name one address, usable as an
+// assembler label.
+fn add_symbol(
+    symbols: &mut BTreeMap<usize, String>,
+    address: usize,
+    name: &str,
+    offset: usize,
+) -> Result<(), ParseImageError> {
+    if !is_identifier(name) {
+        return error(offset, format!("invalid symbol name `{}`", name));
+    }
+    if let Some(existing) = symbols.get(&address) {
+        return error(
+            offset,
+            format!(
+                "`{}` and `{}` both name address {}",
+                existing, name, address
+            ),
+        );
+    }
+    if symbols.values().any(|existing| existing == name) {
+        return error(offset, format!("duplicate symbol `{}`", name));
+    }
+    symbols.insert(address, name.to_string());
+    Ok(())
+}
+
+impl Image {
+    pub fn new(intcode: &[i128]) -> Self {
+        let width = if intcode.iter().all(|&word| fits(word, 64)) {
+            64
+        } else {
+            128
+        };
+        Image {
+            intcode: intcode.to_vec(),
+            width,
+            entry: 0,
+            symbols: BTreeMap::new(),
+            lines: BTreeMap::new(),
+        }
+    }
+
+    pub fn program(&self, inputs: &[i128]) -> Program {
+        let mut program = Program::new(&self.intcode, inputs);
+        program.ip = self.entry;
+        if self.symbols.is_empty() && self.lines.is_empty() {
+            program
+        } else {
+            program.with_symbols(self.source_map())
+        }
+    }
+
+    // Items end where the next line starts, as assembled code is contiguous;
+    // labelled items without lines are decoded.
+    pub fn source_map(&self) -> SourceMap {
+        let starts = self.lines.keys().copied().collect::<Vec<_>>();
+        let mut ends = starts
+            .iter()
+            .zip(starts.iter().skip(1).chain(&[self.intcode.len()]))
+            .map(|(&start, &end)| (start, end))
+            .collect::<BTreeMap<_, _>>();
+        for &address in self.symbols.keys() {
+            ends.entry(address).or_insert_with(|| {
+                address + decode(&self.intcode, address).map_or(1, |opcode| size(&opcode))
+            });
+        }
+        SourceMap {
+            labels: self.symbols.clone(),
+            lines: self.lines.clone(),
+            ends,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(self.width as u8);
+        write_varint(&mut bytes, self.entry as u128);
+        write_varint(&mut bytes, self.symbols.len() as u128);
+        for (&address, name) in &self.symbols {
+            write_varint(&mut bytes, name.len() as u128);
+            bytes.extend_from_slice(name.as_bytes());
+            write_varint(&mut bytes, address as u128);
+        }
+        write_varint(&mut bytes, self.lines.len() as u128);
+        for (&address, &line) in &self.lines {
+            write_varint(&mut bytes, address as u128);
+            write_varint(&mut bytes, line as u128);
+        }
+        write_varint(&mut bytes, self.intcode.len() as u128);
+        for &word in &self.intcode {
+            write_varint(&mut bytes, zigzag(word));
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseImageError> {
+        if !bytes.starts_with(MAGIC) {
+            return error(0, "not a binary intcode program");
+        }
+        let mut reader = Reader { bytes, offset: 4 };
+        let version = reader.byte()?;
+        if !(1..=VERSION).contains(&version) {
+            return error(4, format!("unsupported version {}", version));
+        }
+        let width = u32::from(reader.byte()?);
+        if !matches!(width, 8 | 16 | 32 | 64 | 128) {
+            return error(5, format!("unsupported word width {}", width));
+        }
+        let entry = reader.usize()?;
+        let mut symbols = BTreeMap::new();
+        for _ in 0..reader.usize()? {
+            let length = reader.usize()?;
+            let start = reader.offset;
+            let name = bytes
+                .get(start..start.saturating_add(length))
+                .map(|name| String::from_utf8(name.to_vec()));
+            let name = match name {
+                Some(Ok(name)) => name,
+                Some(Err(_)) => return error(start, "symbol name is not UTF-8"),
+                None => return error(bytes.len(), "unexpected end of file"),
+            };
+            reader.offset += length;
+            let offset = reader.offset;
+            add_symbol(&mut symbols, reader.usize()?, &name, offset)?;
+        }
+        let mut lines = BTreeMap::new();
+        if version >= 2 {
+            for _ in 0..reader.usize()? {
+                lines.insert(reader.usize()?, reader.usize()?);
+            }
+        }
+        let count = reader.usize()?;
+        let mut intcode = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            let start = reader.offset;
+            let word = unzigzag(reader.varint()?);
+            if !fits(word, width) {
+                return error(start, format!("{} does not fit in {} bits", word, width));
+            }
+            intcode.push(word);
+        }
+        if reader.offset != bytes.len() {
+            return error(reader.offset, "trailing data");
+        }
+        Ok(Image {
+            intcode,
+            width,
+            entry,
+            symbols,
+            lines,
+        })
+    }
+
+    // Assembly source, keeping its labels as symbols and its line numbers.
+    pub fn assemble(source: &str) -> Result<Self, AsmError> {
+        let (intcode, symbols) = assemble_with_symbols(source)?;
+        Ok(Image {
+            symbols: symbols.labels,
+            lines: symbols.lines,
+            ..Image::new(&intcode)
+        })
+    }
+
+    // Reads either format, telling them apart by the binary magic, or
+    // assembles `.asm` files.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|extension| extension == "asm") {
+            return Image::assemble(&fs::read_to_string(path)?)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        let bytes = fs::read(path)?;
+        let image = if bytes.starts_with(MAGIC) {
+            Image::from_bytes(&bytes)
+        } else {
+            String::from_utf8(bytes)
+                .map_err(|error| ParseImageError {
+                    offset: error.utf8_error().valid_up_to(),
+                    message: "not UTF-8".to_string(),
+                })
+                .and_then(|text| text.parse())
+        };
+        image.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn save_text(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn save_binary(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    fn directive(&mut self, line: &str, offset: usize) -> Result<(), ParseImageError> {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let number = |index: usize| {
+            parts
+                .get(index)
+                .and_then(|value| value.parse::<usize>().ok())
+                .ok_or_else(|| ParseImageError {
+                    offset,
+                    message: format!("invalid directive `#!{}`", line),
+                })
+        };
+        match parts.first().copied() {
+            Some("width") if parts.len() == 2 => {
+                let width = number(1)?;
+                if !matches!(width, 8 | 16 | 32 | 64 | 128) {
+                    return error(offset, format!("unsupported word width {}", width));
+                }
+                self.width = width as u32;
+            }
+            Some("entry") if parts.len() == 2 => self.entry = number(1)?,
+            Some("symbol") if parts.len() == 3 => {
+                add_symbol(&mut self.symbols, number(2)?, parts[1], offset)?
+            }
+            Some("line") if parts.len() == 3 => {
+                self.lines.insert(number(2)?, number(1)?);
+            }
+            _ => return error(offset, format!("invalid directive `#!{}`", line)),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Image {
+    type Err = ParseImageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // a width of 0 until a directive sets one
+        let mut image = Image {
+            width: 0,
+            ..Image::new(&[])
+        };
+        let mut offsets = Vec::new();
+        let mut line_start = 0;
+        for line in s.split_inclusive('\n') {
+            let code = match line.find('#') {
+                Some(index) => {
+                    if let Some(directive) = line[index + 1..].strip_prefix('!') {
+                        image.directive(directive.trim(), line_start + index)?;
+                    }
+                    &line[..index]
+                }
+                None => line,
+            };
+            let mut word_start = line_start;
+            for word in code.split(',') {
+                let offset = word_start + word.len() - word.trim_start().len();
+                word_start += word.len() + 1;
+                let word = word.trim();
+                if word.is_empty() {
+                    continue;
+                }
+                match word.parse() {
+                    Ok(value) => image.intcode.push(value),
+                    Err(_) => return error(offset, format!("invalid word `{}`", word)),
+                }
+                offsets.push(offset);
+            }
+            line_start += line.len();
+        }
+        if image.width == 0 {
+            image.width = Image::new(&image.intcode).width;
+        }
+        let width = image.width;
+        if let Some((&word, &offset)) = image
+            .intcode
+            .iter()
+            .zip(&offsets)
+            .find(|(&word, _)| !fits(word, width))
+        {
+            return error(offset, format!("{} does not fit in {} bits", word, width));
+        }
+        Ok(image)
+    }
+}
+
+impl fmt::Display for Image {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "#!width {}", self.width)?;
+        if self.entry != 0 {
+            writeln!(f, "#!entry {}", self.entry)?;
+        }
+        for (address, name) in &self.symbols {
+            writeln!(f, "#!symbol {} {}", name, address)?;
+        }
+        for (address, line) in &self.lines {
+            writeln!(f, "#!line {} {}", line, address)?;
+        }
+        let lines = self
+            .intcode
+            .chunks(WORDS_PER_LINE)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(i128::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>();
+        writeln!(f, "{}", lines.join(",\n"))
+    }
+}
+
+#[test]
+fn test_text_format() {
+    let text = "#!entry 2\n# a comment, with a comma\n1, 0,0,0, # add\n\n  99,\n#!symbol start 2\n";
+    let image = text.parse::<Image>().unwrap();
+    assert_eq!(image.intcode, vec![1, 0, 0, 0, 99]);
+    assert_eq!((image.width, image.entry), (64, 2));
+    assert_eq!(image.symbols.get(&2).map(String::as_str), Some("start"));
+    assert_eq!(image.program(&[]).ip, 2);
+    assert_eq!(image.program(&[]).locate(2), "start");
+    assert_eq!(image.program(&[]).locate(3), "ip=3");
+    assert_eq!(image.to_string().parse(), Ok(image));
+
+    let error = "1,0,\n 0,x,99".parse::<Image>().unwrap_err();
+    assert_eq!(
+        (error.offset, error.message.as_str()),
+        (8, "invalid word `x`")
+    );
+    assert_eq!("#!width 8\n1,300".parse::<Image>().unwrap_err().offset, 12);
+    assert_eq!("1\n#!bogus".parse::<Image>().unwrap_err().offset, 2);
+}
+
+#[test]
+fn test_binary_format() {
+    let mut image = Image::new(&[109, -1, 204, 1 << 70, 99]);
+    image.entry = 2;
+    image.symbols.insert(4, "halt".to_string());
+    assert_eq!(image.width, 128);
+    let bytes = image.to_bytes();
+    assert_eq!(&bytes[..6], b"ICB\0\x02\x80");
+    assert_eq!(Image::from_bytes(&bytes), Ok(image.clone()));
+    assert_eq!(image.to_string().parse(), Ok(image));
+
+    for word in &[0, -1, 1, i128::MAX, i128::MIN] {
+        assert_eq!(unzigzag(zigzag(*word)), *word);
+        let bytes = Image::new(&[*word]).to_bytes();
+        assert_eq!(Image::from_bytes(&bytes).unwrap().intcode, vec![*word]);
+    }
+    let bytes = Image::new(&[1, 2, 3]).to_bytes();
+    assert_eq!(
+        Image::from_bytes(&bytes[..bytes.len() - 1])
+            .unwrap_err()
+            .offset,
+        bytes.len() - 1
+    );
+    assert!(Image::from_bytes(b"1,2,3").is_err());
+}
+
+#[test]
+fn test_duplicate_symbols() {
+    let error = "#!symbol a 0\n#!symbol b 0\n99"
+        .parse::<Image>()
+        .unwrap_err();
+    assert_eq!(
+        (error.offset, error.message.as_str()),
+        (13, "`a` and `b` both name address 0")
+    );
+    let error = "99,99\n#!symbol a 0\n#!symbol a 1"
+        .parse::<Image>()
+        .unwrap_err();
+    assert_eq!(
+        (error.offset, error.message.as_str()),
+        (19, "duplicate symbol `a`")
+    );
+
+    let bytes = b"ICB\0\x02\x40\x00\x02\x01a\x00\x01b\x00\x00\x01\xc6\x01";
+    assert_eq!(Image::from_bytes(&bytes[..]).unwrap_err().offset, 13);
+    let error = "99\n#!symbol 1a 0".parse::<Image>().unwrap_err();
+    assert_eq!(
+        (error.offset, error.message.as_str()),
+        (3, "invalid symbol name `1a`")
+    );
+}
+
+#[test]
+fn test_assembled_lines() {
+    let image = Image::assemble("start: in [9]\n\nout [9]\nhlt").unwrap();
+    assert_eq!(
+        image.lines,
+        vec![(0, 1), (2, 3), (4, 4)].into_iter().collect()
+    );
+    let symbols = image.source_map();
+    assert_eq!(symbols.lines, image.lines);
+    assert_eq!(symbols.locate(3), "ip=3 (line 3)");
+    assert_eq!(symbols.locate(5), "ip=5");
+    assert_eq!(image.to_string().parse(), Ok(image.clone()));
+    assert_eq!(Image::from_bytes(&image.to_bytes()), Ok(image.clone()));
+
+    // version 1 files have no line table
+    let mut bytes = Image::new(&[99]).to_bytes();
+    bytes[4] = 1;
+    bytes.remove(8);
+    assert_eq!(Image::from_bytes(&bytes), Ok(Image::new(&[99])));
+}
@@ -0,0 +1,53 @@
+// lets transpiled code, which names the `intcode` crate, be tested in here
+extern crate self as intcode;
+
+mod assembler;
+mod budget;
+mod cfg;
+mod device;
+mod disassembler;
+mod error;
+mod history;
+mod image;
+mod memory;
+mod network;
+mod opcode;
+pub mod ops;
+mod optimizer;
+mod profile;
+mod program;
+mod session;
+mod snapshot;
+mod symbolic;
+mod symbols;
+mod transpile;
+mod word;
+
+pub use assembler::{assemble, assemble_with_symbols, AsmError};
+pub use budget::Budget;
+pub use cfg::{BasicBlock, ControlFlowGraph, Function, Loop, Successor};
+pub use device::{
+    AsciiReader, AsciiWriter, InputDevice, InputFn, LineReader, LineWriter, OutputDevice, OutputFn,
+    Recorder,
+};
+pub use disassembler::{
+    decode, disassemble, disassemble_from, disassemble_image, disassemble_instruction, reachable,
+    Disassembly, Line, LineKind,
+};
+pub use error::VmError;
+pub use history::{History, Undo, DEFAULT_HISTORY_LIMIT};
+pub use image::{Image, ParseImageError};
+pub use memory::{Memory, DEFAULT_DENSE_LIMIT, DEFAULT_MEMORY_LIMIT};
+pub use network::{run_machine, Network, NetworkError};
+pub use opcode::{code_from_mnemonic, Opcode, ParameterMode, Parameters, MAX_PARAMETERS};
+pub use optimizer::Optimized;
+pub use profile::Profile;
+pub use program::{Program, RunState};
+pub use session::{Event, ParseSessionError, ReplayError, Session};
+pub use snapshot::{ParseSnapshotError, Snapshot};
+pub use symbolic::{
+    solve, Expr, Method, Solution, Source, SymbolicError, SymbolicProgram, Target, Unknown,
+};
+pub use symbols::SourceMap;
+pub use transpile::{transpile, transpile_image};
+pub use word::{Overflow, Word};
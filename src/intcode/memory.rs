@@ -0,0 +1,366 @@
+use crate::error::VmError;
+use crate::opcode::{Opcode, MAX_PARAMETERS};
+use crate::program::Program;
+use crate::word::Word;
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
+const PAGE_SIZE: usize = 1024;
+pub const DEFAULT_DENSE_LIMIT: usize = 1 << 20;
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 24;
+
+// Addresses below `dense_limit` live in a plain Vec, anything above goes into
+// lazily allocated pages. `limit` caps the number of allocated cells.
+// Instructions decoded from the dense part are cached until a write touches
+// one of their words, and writes into a watched region mark it as modified.
+#[derive(Clone, Debug)]
+pub struct Memory<W = i128> {
+    dense: Vec<W>,
+    pages: HashMap<usize, Box<[W]>>,
+    len: usize,
+    dense_limit: usize,
+    limit: Option<usize>,
+    zero: W,
+    decoded: Vec<Option<Opcode>>,
+    decode_cache: bool,
+    watching: bool,
+    // the start of the watched region each dense address belongs to
+    watched: Vec<Option<usize>>,
+    modified: Vec<bool>,
+}
+
+impl<W: Word> Default for Memory<W> {
+    fn default() -> Self {
+        Memory::from_words(&[])
+    }
+}
+
+impl Memory {
+    pub fn new(intcode: &[i128]) -> Self {
+        Memory::from_words(intcode)
+    }
+}
+
+impl<W: Word> Memory<W> {
+    pub fn from_words(intcode: &[W]) -> Self {
+        Memory::with_limits(intcode, DEFAULT_DENSE_LIMIT, Some(DEFAULT_MEMORY_LIMIT))
+    }
+
+    pub fn with_limits(intcode: &[W], dense_limit: usize, limit: Option<usize>) -> Self {
+        let mut memory = Memory {
+            dense: Vec::new(),
+            pages: HashMap::new(),
+            len: 0,
+            dense_limit,
+            limit,
+            zero: W::default(),
+            decoded: Vec::new(),
+            decode_cache: true,
+            watching: false,
+            watched: Vec::new(),
+            modified: Vec::new(),
+        };
+        memory.load(intcode, &[]);
+        memory
+    }
+
+    pub fn load(&mut self, dense: &[W], sparse: &[(usize, W)]) {
+        let limit = self.limit.take();
+        let split = dense.len().min(self.dense_limit);
+        self.dense.clear();
+        self.dense.extend_from_slice(&dense[..split]);
+        self.pages.clear();
+        self.decoded.clear();
+        self.watching = false;
+        self.watched.clear();
+        self.modified.clear();
+        self.len = dense.len();
+        for (address, value) in dense.iter().enumerate().skip(split) {
+            if !value.is_zero() {
+                self[address] = value.clone();
+            }
+        }
+        for (address, value) in sparse {
+            self[*address] = value.clone();
+        }
+        self.limit = limit;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn allocated(&self) -> usize {
+        self.dense.len() + self.pages.len() * PAGE_SIZE
+    }
+
+    pub fn dense_limit(&self) -> usize {
+        self.dense_limit
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn set_dense_limit(&mut self, dense_limit: usize) {
+        let dense = std::mem::take(&mut self.dense);
+        let sparse = self.sparse();
+        let len = self.len;
+        self.dense_limit = dense_limit;
+        self.load(&dense, &sparse);
+        self.len = self.len.max(len);
+    }
+
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled;
+        self.decoded.clear();
+    }
+
+    pub fn decode(&mut self, address: usize) -> Result<Opcode, VmError> {
+        if let Some(&Some(opcode)) = self.decoded.get(address) {
+            return Ok(opcode);
+        }
+        let opcode = Opcode::new(address, self[address].saturating_i128())?;
+        if self.decode_cache && address < self.dense.len() {
+            if address >= self.decoded.len() {
+                self.decoded.resize(self.dense.len(), None);
+            }
+            self.decoded[address] = Some(opcode);
+        }
+        Ok(opcode)
+    }
+
+    pub(crate) fn is_watching(&self) -> bool {
+        self.watching
+    }
+
+    // Watches each `(start, end, modified)` range as one region, until the
+    // next `load`.
+    pub(crate) fn watch(&mut self, regions: impl IntoIterator<Item = (usize, usize, bool)>) {
+        self.watching = true;
+        for (start, end, modified) in regions {
+            if end > self.watched.len() {
+                self.watched.resize(end, None);
+                self.modified.resize(end, false);
+            }
+            for region in &mut self.watched[start..end] {
+                *region = Some(start);
+            }
+            self.modified[start] = modified;
+        }
+    }
+
+    pub(crate) fn is_modified(&self, start: usize) -> bool {
+        self.modified[start]
+    }
+
+    pub(crate) fn set_modified(&mut self, start: usize, modified: bool) {
+        self.modified[start] = modified;
+    }
+
+    pub fn dense(&self) -> &[W] {
+        &self.dense
+    }
+
+    pub fn sparse(&self) -> Vec<(usize, W)> {
+        let mut cells = self
+            .pages
+            .iter()
+            .flat_map(|(&page, cells)| {
+                cells
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, value)| !value.is_zero())
+                    .map(move |(offset, value)| (page * PAGE_SIZE + offset, value.clone()))
+            })
+            .collect::<Vec<_>>();
+        cells.sort_unstable_by_key(|&(address, _)| address);
+        cells
+    }
+
+    // Every cell below `len`, or None where there are more of them than the
+    // memory limit allows, as after a single write far above the program.
+    pub fn to_vec(&self) -> Option<Vec<W>> {
+        if self
+            .limit
+            .is_some_and(|limit| self.len > limit.max(self.allocated()))
+        {
+            return None;
+        }
+        let mut cells = Vec::new();
+        cells.try_reserve_exact(self.len).ok()?;
+        cells.extend((0..self.len).map(|address| self.get(address)));
+        Some(cells)
+    }
+
+    pub fn get(&self, address: usize) -> W {
+        self[address].clone()
+    }
+
+    fn cell(&self, address: usize) -> Option<&W> {
+        if address < self.dense_limit {
+            self.dense.get(address)
+        } else {
+            self.pages
+                .get(&(address / PAGE_SIZE))
+                .map(|page| &page[address % PAGE_SIZE])
+        }
+    }
+
+    // None when the cell would go over the memory limit, or past the end of
+    // the address space.
+    pub fn get_mut(&mut self, address: usize) -> Option<&mut W> {
+        let end = address.checked_add(1)?;
+        let stale = address.saturating_sub(MAX_PARAMETERS)..end.min(self.decoded.len());
+        for opcode in self.decoded.get_mut(stale).unwrap_or_default() {
+            *opcode = None;
+        }
+        if let Some(&Some(start)) = self.watched.get(address) {
+            self.modified[start] = true;
+        }
+        let within_limit = |cells: Option<usize>| {
+            cells.is_some_and(|cells| self.limit.is_none_or(|limit| cells <= limit))
+        };
+        if address < self.dense_limit {
+            if address >= self.dense.len() {
+                let paged = self.pages.len().checked_mul(PAGE_SIZE);
+                if !within_limit(paged.and_then(|paged| paged.checked_add(end))) {
+                    return None;
+                }
+                self.dense.resize(end, W::default());
+            }
+            self.len = self.len.max(end);
+            Some(&mut self.dense[address])
+        } else {
+            let page = address / PAGE_SIZE;
+            if !self.pages.contains_key(&page)
+                && !within_limit(self.allocated().checked_add(PAGE_SIZE))
+            {
+                return None;
+            }
+            self.len = self.len.max(end);
+            let cells = self
+                .pages
+                .entry(page)
+                .or_insert_with(|| vec![W::default(); PAGE_SIZE].into_boxed_slice());
+            Some(&mut cells[address % PAGE_SIZE])
+        }
+    }
+}
+
+impl<W: Word> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, address: usize) -> &W {
+        self.cell(address).unwrap_or(&self.zero)
+    }
+}
+
+impl<W: Word> IndexMut<usize> for Memory<W> {
+    fn index_mut(&mut self, address: usize) -> &mut W {
+        self.get_mut(address)
+            .unwrap_or_else(|| panic!("memory limit exceeded writing address {}", address))
+    }
+}
+
+impl<W: Word> From<&[W]> for Memory<W> {
+    fn from(intcode: &[W]) -> Self {
+        Memory::from_words(intcode)
+    }
+}
+
+impl<W: Word> Program<W> {
+    pub fn with_memory_limit(mut self, cells: Option<usize>) -> Self {
+        self.intcode.set_limit(cells);
+        self
+    }
+
+    pub fn with_dense_limit(mut self, addresses: usize) -> Self {
+        self.intcode.set_dense_limit(addresses);
+        self
+    }
+
+    pub fn with_decode_cache(mut self, enabled: bool) -> Self {
+        self.intcode.set_decode_cache(enabled);
+        self
+    }
+}
+
+#[test]
+fn test_sparse_memory() {
+    let mut memory = Memory::<i128>::with_limits(&[1, 2, 0, 4], 2, Some(2 + PAGE_SIZE));
+    assert_eq!(memory.dense(), &[1, 2]);
+    assert_eq!(memory.sparse(), vec![(3, 4)]);
+    assert_eq!(memory.len(), 4);
+    assert_eq!(memory.to_vec(), Some(vec![1, 2, 0, 4]));
+    assert_eq!(memory[1_000_000_000_000], 0);
+    assert_eq!(memory.allocated(), 2 + PAGE_SIZE);
+    assert_eq!(memory.get_mut(1_000_000_000_000), None);
+    memory[PAGE_SIZE - 1] = 9;
+    assert_eq!(memory.sparse(), vec![(3, 4), (PAGE_SIZE - 1, 9)]);
+    memory.set_dense_limit(PAGE_SIZE);
+    assert_eq!(memory.dense().len(), PAGE_SIZE);
+    assert_eq!(memory.sparse(), vec![]);
+    assert_eq!(memory.len(), PAGE_SIZE);
+    assert_eq!(memory[3], 4);
+}
+
+#[test]
+fn test_memory_limit() {
+    use crate::VmError;
+
+    let intcode = [1101, 1, 2, 1_000_000_000_000, 4, 1_000_000_000_000, 99];
+    let mut program = Program::new(&intcode, &[]);
+    assert_eq!(program.next_output(&[]), Ok(Some(3)));
+    assert_eq!(program.intcode.allocated(), intcode.len() + PAGE_SIZE);
+    assert_eq!(program.intcode.len(), 1_000_000_000_001);
+    assert_eq!(program.intcode.to_vec(), None);
+    let mut program = Program::new(&intcode, &[]).with_memory_limit(Some(16));
+    assert_eq!(
+        program.next_output(&[]),
+        Err(VmError::MemoryLimit {
+            ip: 0,
+            address: 1_000_000_000_000
+        })
+    );
+    let mut program = Program::new(&[1101, 1, 2, 100, 4, 100, 99], &[]).with_dense_limit(0);
+    assert_eq!(program.next_output(&[]), Ok(Some(3)));
+    assert!(program.intcode.dense().is_empty());
+}
+
+#[test]
+fn test_decode_cache_sees_writes() {
+    let mut memory = Memory::new(&[1002, 0, 0, 0, 99]);
+    assert_eq!(memory.decode(0).unwrap().code, 2);
+    assert_eq!(memory.decoded[0].map(|opcode| opcode.code), Some(2));
+    memory[3] = 7;
+    assert_eq!(memory.decoded[0], None);
+    memory[0] = 1101;
+    assert_eq!(memory.decode(0).unwrap().code, 1);
+    assert_eq!(memory.decode(4).unwrap().code, 99);
+    memory[8] = 5;
+    assert_eq!(memory.decode(4).unwrap().code, 99);
+}
+
+#[test]
+fn test_last_address() {
+    use crate::VmError;
+
+    let address = usize::MAX;
+    let mut program = Program::new(&[1101, 1, 1, address as i128, 99], &[]);
+    assert_eq!(
+        program.run(&mut std::collections::VecDeque::new()),
+        Err(VmError::MemoryLimit { ip: 0, address })
+    );
+    let mut memory = Memory::<i128>::with_limits(&[1, 2], usize::MAX, None);
+    assert_eq!(memory.get_mut(address), None);
+    assert_eq!(memory[address], 0);
+}
@@ -0,0 +1,282 @@
+use crate::error::VmError;
+use crate::program::{Program, RunState};
+use crate::symbols::locate;
+use async_std::task;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkError {
+    UnknownMachine(String),
+    DuplicateMachine(String),
+    // `location` is the faulting ip shown through the machine's symbols
+    Machine {
+        name: String,
+        error: VmError,
+        location: String,
+    },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::UnknownMachine(name) => write!(f, "unknown machine `{}`", name),
+            NetworkError::DuplicateMachine(name) => write!(f, "duplicate machine `{}`", name),
+            NetworkError::Machine {
+                name,
+                error,
+                location,
+            } => write!(f, "machine `{}`: {}", name, error.message(location)),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+// Outputs sent to a machine that has already finished are dropped.
+pub async fn run_machine(
+    mut program: Program,
+    mut input: Receiver<i128>,
+    mut outputs: Vec<Sender<i128>>,
+) -> Result<Vec<i128>, VmError> {
+    let mut produced = Vec::new();
+    loop {
+        match program.resume()? {
+            RunState::Output(value) => {
+                for output in &mut outputs {
+                    let _ = output.send(value).await;
+                }
+                produced.push(value);
+            }
+            RunState::AwaitingInput => match input.next().await {
+                Some(value) => program.push_input(value),
+                None => return Err(VmError::InputStarvation { ip: program.ip }),
+            },
+            RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: program.ip }),
+            RunState::Halted => return Ok(produced),
+        }
+    }
+}
+
+pub struct Network {
+    machines: Vec<(String, Program)>,
+    links: Vec<(String, String)>,
+    capacity: usize,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            machines: Vec::new(),
+            links: Vec::new(),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn chain(machines: impl IntoIterator<Item = (String, Program)>) -> Self {
+        let mut network = Self::new();
+        for (name, program) in machines {
+            if let Some((previous, _)) = network.machines.last() {
+                let previous = previous.clone();
+                network.connect(&previous, &name);
+            }
+            network.add_machine(&name, program);
+        }
+        network
+    }
+
+    pub fn ring(machines: impl IntoIterator<Item = (String, Program)>) -> Self {
+        let mut network = Self::chain(machines);
+        if let (Some((first, _)), Some((last, _))) =
+            (network.machines.first(), network.machines.last())
+        {
+            let (first, last) = (first.clone(), last.clone());
+            network.connect(&last, &first);
+        }
+        network
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn add_machine(&mut self, name: &str, program: Program) -> &mut Self {
+        self.machines.push((name.to_string(), program));
+        self
+    }
+
+    pub fn connect(&mut self, from: &str, to: &str) -> &mut Self {
+        self.links.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    pub fn push_input(&mut self, name: &str, value: i128) -> Result<&mut Self, NetworkError> {
+        self.machines
+            .iter_mut()
+            .find(|(machine, _)| machine == name)
+            .ok_or_else(|| NetworkError::UnknownMachine(name.to_string()))?
+            .1
+            .push_input(value);
+        Ok(self)
+    }
+
+    pub async fn run(self) -> Result<HashMap<String, Vec<i128>>, NetworkError> {
+        let mut names = HashSet::new();
+        for (name, _) in &self.machines {
+            if !names.insert(name) {
+                return Err(NetworkError::DuplicateMachine(name.clone()));
+            }
+        }
+        let mut channels = self
+            .machines
+            .iter()
+            .map(|(name, _)| (name.clone(), channel(self.capacity)))
+            .collect::<HashMap<_, _>>();
+        let mut outputs = HashMap::<_, Vec<_>>::new();
+        for (from, to) in &self.links {
+            if !channels.contains_key(from) {
+                return Err(NetworkError::UnknownMachine(from.clone()));
+            }
+            let (sender, _) = channels
+                .get(to)
+                .ok_or_else(|| NetworkError::UnknownMachine(to.clone()))?;
+            outputs
+                .entry(from.clone())
+                .or_default()
+                .push(sender.clone());
+        }
+        // each receiver goes with its machine's task, so that sends to it fail
+        // rather than block once the machine is done
+        let mut running = self
+            .machines
+            .into_iter()
+            .map(|(name, program)| {
+                let (_, input) = channels.remove(&name).unwrap();
+                let outputs = outputs.remove(&name).unwrap_or_default();
+                let symbols = program.symbols.clone();
+                let handle = task::spawn(run_machine(program, input, outputs));
+                async move { (name, symbols, handle.await) }
+            })
+            .collect::<FuturesUnordered<_>>();
+        // the first machine to fail is the cause, the others fail on it
+        let mut results = HashMap::new();
+        while let Some((name, symbols, result)) = running.next().await {
+            match result {
+                Ok(produced) => results.insert(name, produced),
+                Err(error) => {
+                    let location = locate(symbols.as_deref(), error.ip());
+                    return Err(NetworkError::Machine {
+                        name,
+                        error,
+                        location,
+                    });
+                }
+            };
+        }
+        Ok(results)
+    }
+}
+
+#[test]
+fn test_chain() {
+    let double = [3, 9, 1002, 9, 2, 9, 4, 9, 99, 0];
+    let mut network = Network::chain(
+        ["a", "b", "c"]
+            .iter()
+            .map(|name| (name.to_string(), Program::new(&double, &[]))),
+    );
+    network.push_input("a", 5).unwrap();
+    let outputs = task::block_on(network.run()).unwrap();
+    assert_eq!(outputs["a"], vec![10]);
+    assert_eq!(outputs["c"], vec![40]);
+}
+
+#[test]
+fn test_graph_fan_in() {
+    let double = [3, 9, 1002, 9, 2, 9, 4, 9, 99, 0];
+    let sum = [3, 11, 3, 12, 1, 11, 12, 11, 4, 11, 99, 0, 0];
+    let mut network = Network::new();
+    network
+        .add_machine("left", Program::new(&double, &[1]))
+        .add_machine("right", Program::new(&double, &[2]))
+        .add_machine("sum", Program::new(&sum, &[]))
+        .connect("left", "sum")
+        .connect("right", "sum");
+    let outputs = task::block_on(network.run()).unwrap();
+    assert_eq!(outputs["sum"], vec![6]);
+}
+
+#[test]
+fn test_unknown_machine() {
+    let mut network = Network::new();
+    network
+        .add_machine("a", Program::new(&[99], &[]))
+        .connect("a", "b");
+    assert_eq!(
+        task::block_on(network.run()),
+        Err(NetworkError::UnknownMachine("b".to_string()))
+    );
+
+    let mut network = Network::new();
+    network
+        .add_machine("a", Program::new(&[99], &[]))
+        .add_machine("a", Program::new(&[99], &[]));
+    assert_eq!(
+        task::block_on(network.run()),
+        Err(NetworkError::DuplicateMachine("a".to_string()))
+    );
+}
+
+#[test]
+fn test_located_error() {
+    let mut labels = std::collections::BTreeMap::new();
+    labels.insert(2, "bad".to_string());
+    let mut network = Network::new();
+    network.add_machine(
+        "a",
+        Program::new(&[104, 1, 42], &[]).with_symbols(crate::SourceMap::new(labels)),
+    );
+    let error = task::block_on(network.run()).unwrap_err();
+    assert_eq!(error.to_string(), "machine `a`: invalid opcode `42` at bad");
+}
+
+#[test]
+fn test_first_failure() {
+    // `a` starves once `b` has failed
+    let network = Network::ring([
+        ("a".to_string(), Program::new(&[104, 1, 3, 0, 99], &[])),
+        ("b".to_string(), Program::new(&[42], &[])),
+    ]);
+    assert_eq!(
+        task::block_on(network.run()),
+        Err(NetworkError::Machine {
+            name: "b".to_string(),
+            error: VmError::InvalidOpcode { ip: 0, opcode: 42 },
+            location: "ip=0".to_string(),
+        })
+    );
+
+    // sends to a machine that halted without reading do not block
+    let network = Network::chain([
+        (
+            "a".to_string(),
+            Program::new(&[104, 1, 104, 2, 104, 3, 104, 4, 99], &[]),
+        ),
+        ("b".to_string(), Program::new(&[99], &[])),
+    ])
+    .with_capacity(1);
+    let outputs = task::block_on(network.run()).unwrap();
+    assert_eq!(outputs["a"], vec![1, 2, 3, 4]);
+}
@@ -0,0 +1,247 @@
+use crate::error::VmError;
+use crate::memory::Memory;
+use crate::word::Word;
+use std::convert::TryFrom;
+
+// (code, mnemonic, parameter count, written parameter)
+const INSTRUCTIONS: &[(usize, &str, usize, Option<usize>)] = &[
+    (1, "add", 3, Some(2)),
+    (2, "mul", 3, Some(2)),
+    (3, "in", 1, Some(0)),
+    (4, "out", 1, None),
+    (5, "jt", 2, None),
+    (6, "jf", 2, None),
+    (7, "lt", 3, Some(2)),
+    (8, "eq", 3, Some(2)),
+    (9, "arb", 1, None),
+    (99, "hlt", 0, None),
+];
+
+pub fn code_from_mnemonic(mnemonic: &str) -> Option<usize> {
+    INSTRUCTIONS
+        .iter()
+        .find(|instruction| instruction.1 == mnemonic)
+        .map(|instruction| instruction.0)
+}
+
+fn instruction(code: usize) -> Option<&'static (usize, &'static str, usize, Option<usize>)> {
+    INSTRUCTIONS
+        .iter()
+        .find(|instruction| instruction.0 == code)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ParameterMode {
+    #[default]
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl ParameterMode {
+    pub fn new(mode: u32) -> Option<Self> {
+        match mode {
+            0 => Some(ParameterMode::Position),
+            1 => Some(ParameterMode::Immediate),
+            2 => Some(ParameterMode::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> i128 {
+        match self {
+            ParameterMode::Position => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        }
+    }
+}
+
+pub const MAX_PARAMETERS: usize = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Parameters(pub [ParameterMode; MAX_PARAMETERS]);
+
+impl Parameters {
+    pub fn new(modes: &[ParameterMode]) -> Self {
+        let mut parameters = Parameters::default();
+        for (slot, &mode) in parameters.0.iter_mut().zip(modes) {
+            *slot = mode;
+        }
+        parameters
+    }
+
+    pub fn mode(&self, index: usize) -> ParameterMode {
+        self.0.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn address<W: Word>(
+        &self,
+        index: usize,
+        intcode: &Memory<W>,
+        ip: usize,
+        relative_base: i128,
+    ) -> Result<usize, VmError> {
+        let parameter = ip + index + 1;
+        let value = intcode[parameter].saturating_i128();
+        let address = match self.mode(index) {
+            ParameterMode::Position => value,
+            ParameterMode::Immediate => return Ok(parameter),
+            ParameterMode::Relative => relative_base.saturating_add(value),
+        };
+        usize::try_from(address).map_err(|_| VmError::NegativeAddress { ip, address })
+    }
+
+    pub fn peek_address<W: Word>(
+        &self,
+        index: usize,
+        intcode: &Memory<W>,
+        ip: usize,
+        relative_base: i128,
+    ) -> Option<usize> {
+        self.address(index, intcode, ip, relative_base).ok()
+    }
+
+    pub fn peek<W: Word>(
+        &self,
+        index: usize,
+        intcode: &Memory<W>,
+        ip: usize,
+        relative_base: i128,
+    ) -> Option<W> {
+        self.peek_address(index, intcode, ip, relative_base)
+            .map(|address| intcode.get(address))
+    }
+
+    pub fn get<W: Word>(
+        &self,
+        index: usize,
+        intcode: &Memory<W>,
+        ip: usize,
+        relative_base: i128,
+    ) -> Result<W, VmError> {
+        let address = self.address(index, intcode, ip, relative_base)?;
+        Ok(intcode.get(address))
+    }
+
+    pub fn get_mut<'a, W: Word>(
+        &self,
+        index: usize,
+        intcode: &'a mut Memory<W>,
+        ip: usize,
+        relative_base: i128,
+    ) -> Result<&'a mut W, VmError> {
+        if self.mode(index) == ParameterMode::Immediate {
+            return Err(VmError::WriteToImmediate { ip });
+        }
+        let address = self.address(index, intcode, ip, relative_base)?;
+        intcode
+            .get_mut(address)
+            .ok_or(VmError::MemoryLimit { ip, address })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Opcode {
+    pub code: usize,
+    pub parameters: Parameters,
+}
+
+impl Opcode {
+    pub fn new(ip: usize, word: i128) -> Result<Self, VmError> {
+        if word < 0 {
+            return Err(VmError::InvalidOpcode { ip, opcode: word });
+        }
+        let mut parameters = Parameters::default();
+        let mut modes = word / 100;
+        let mut index = 0;
+        while modes > 0 {
+            let mode = ParameterMode::new((modes % 10) as u32)
+                .ok_or(VmError::BadParameterMode { ip, opcode: word })?;
+            if let Some(slot) = parameters.0.get_mut(index) {
+                *slot = mode;
+            }
+            modes /= 10;
+            index += 1;
+        }
+        Ok(Opcode {
+            code: (word % 100) as usize,
+            parameters,
+        })
+    }
+
+    pub fn encode(code: usize, modes: &[ParameterMode]) -> i128 {
+        modes
+            .iter()
+            .rev()
+            .fold(0, |word, mode| word * 10 + mode.digit())
+            * 100
+            + code as i128
+    }
+
+    pub fn mnemonic(&self) -> Option<&'static str> {
+        instruction(self.code).map(|instruction| instruction.1)
+    }
+
+    pub fn parameter_count(&self) -> Option<usize> {
+        instruction(self.code).map(|instruction| instruction.2)
+    }
+
+    pub fn written_parameter(&self) -> Option<usize> {
+        instruction(self.code).and_then(|instruction| instruction.3)
+    }
+}
+
+#[test]
+fn test_opcode_new() {
+    let opcode = Opcode::new(0, 1002).unwrap();
+    assert_eq!(opcode.code, 2);
+    assert_eq!(opcode.parameters.mode(0), ParameterMode::Position);
+    assert_eq!(opcode.parameters.mode(1), ParameterMode::Immediate);
+    assert_eq!(opcode.parameters.mode(2), ParameterMode::Position);
+    let opcode = Opcode::new(0, 21101).unwrap();
+    assert_eq!(opcode.code, 1);
+    assert_eq!(opcode.parameters.mode(2), ParameterMode::Relative);
+    assert_eq!(Opcode::new(0, 99).unwrap().code, 99);
+}
+
+#[test]
+fn test_opcode_new_errors() {
+    assert_eq!(
+        Opcode::new(4, 301),
+        Err(VmError::BadParameterMode { ip: 4, opcode: 301 })
+    );
+    assert_eq!(
+        Opcode::new(7, -1),
+        Err(VmError::InvalidOpcode { ip: 7, opcode: -1 })
+    );
+    assert_eq!(
+        Opcode::new(0, 3_001_101),
+        Err(VmError::BadParameterMode {
+            ip: 0,
+            opcode: 3_001_101
+        })
+    );
+}
+
+#[test]
+fn test_opcode_encode() {
+    use ParameterMode::*;
+    assert_eq!(Opcode::encode(2, &[Position, Immediate, Position]), 1002);
+    assert_eq!(Opcode::encode(1, &[Immediate, Immediate, Relative]), 21101);
+    assert_eq!(Opcode::encode(99, &[]), 99);
+    let opcode = Opcode::new(0, 21101).unwrap();
+    assert_eq!(opcode.mnemonic(), Some("add"));
+    assert_eq!(opcode.parameter_count(), Some(3));
+    assert_eq!(opcode.written_parameter(), Some(2));
+}
+
+#[test]
+fn test_parameters_peek() {
+    let intcode = Memory::new(&[21101, 4, -1, 7]);
+    let parameters = Opcode::new(0, intcode[0]).unwrap().parameters;
+    assert_eq!(parameters.peek(0, &intcode, 0, 0), Some(4));
+    assert_eq!(parameters.peek_address(2, &intcode, 0, 3), Some(10));
+    assert_eq!(parameters.peek(2, &intcode, 0, 3), Some(0));
+    assert_eq!(parameters.peek(2, &intcode, 0, -10), None);
+}
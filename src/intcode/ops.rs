@@ -0,0 +1,174 @@
+use crate::error::VmError;
+use crate::memory::Memory;
+use crate::opcode::Parameters;
+use crate::word::{Overflow, Word};
+use log::debug;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+fn arithmetic<W>(
+    checked: Option<W>,
+    wrapped: impl FnOnce() -> W,
+    overflow: Overflow,
+    ip: usize,
+) -> Result<W, VmError> {
+    match (checked, overflow) {
+        (Some(value), _) => Ok(value),
+        (None, Overflow::Wrap) => Ok(wrapped()),
+        (None, _) => Err(VmError::Overflow { ip }),
+    }
+}
+
+pub fn add<W: Word>(
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+    overflow: Overflow,
+) -> Result<(), VmError> {
+    let operand1 = parameters.get(0, intcode, *ip, *relative_base)?;
+    let operand2 = parameters.get(1, intcode, *ip, *relative_base)?;
+    let output = parameters.get_mut(2, intcode, *ip, *relative_base)?;
+    *output = arithmetic(
+        operand1.checked_add(&operand2),
+        || operand1.wrapping_add(&operand2),
+        overflow,
+        *ip,
+    )?;
+    *ip += 4;
+    Ok(())
+}
+
+pub fn mul<W: Word>(
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+    overflow: Overflow,
+) -> Result<(), VmError> {
+    let operand1 = parameters.get(0, intcode, *ip, *relative_base)?;
+    let operand2 = parameters.get(1, intcode, *ip, *relative_base)?;
+    let output = parameters.get_mut(2, intcode, *ip, *relative_base)?;
+    *output = arithmetic(
+        operand1.checked_mul(&operand2),
+        || operand1.wrapping_mul(&operand2),
+        overflow,
+        *ip,
+    )?;
+    *ip += 4;
+    Ok(())
+}
+
+pub fn input<W: Word>(
+    next_inputs: &mut VecDeque<W>,
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+) -> Result<(), VmError> {
+    let input = next_inputs
+        .pop_front()
+        .ok_or(VmError::InputStarvation { ip: *ip })?;
+    let output = parameters.get_mut(0, intcode, *ip, *relative_base)?;
+    debug!("ip={} input {}", ip, input);
+    *output = input;
+    *ip += 2;
+    Ok(())
+}
+
+pub fn output<W: Word>(
+    stdout: &mut Vec<W>,
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+) -> Result<(), VmError> {
+    let operand1 = parameters.get(0, intcode, *ip, *relative_base)?;
+    debug!("ip={} output {}", ip, operand1);
+    stdout.push(operand1);
+    *ip += 2;
+    Ok(())
+}
+
+pub fn jump_if<W: Word>(
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+) -> Result<(), VmError> {
+    let condition = parameters.get(0, intcode, *ip, *relative_base)?;
+    let jump_addr = parameters.get(1, intcode, *ip, *relative_base)?;
+    if !condition.is_zero() {
+        *ip =
+            usize::try_from(jump_addr.saturating_i128()).map_err(|_| VmError::NegativeAddress {
+                ip: *ip,
+                address: jump_addr.saturating_i128(),
+            })?;
+    } else {
+        *ip += 3;
+    }
+    Ok(())
+}
+
+pub fn jump_unless<W: Word>(
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+) -> Result<(), VmError> {
+    let condition = parameters.get(0, intcode, *ip, *relative_base)?;
+    let jump_addr = parameters.get(1, intcode, *ip, *relative_base)?;
+    if condition.is_zero() {
+        *ip =
+            usize::try_from(jump_addr.saturating_i128()).map_err(|_| VmError::NegativeAddress {
+                ip: *ip,
+                address: jump_addr.saturating_i128(),
+            })?;
+    } else {
+        *ip += 3;
+    }
+    Ok(())
+}
+
+pub fn is_less_than<W: Word>(
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+) -> Result<(), VmError> {
+    let operand1 = parameters.get(0, intcode, *ip, *relative_base)?;
+    let operand2 = parameters.get(1, intcode, *ip, *relative_base)?;
+    let output = parameters.get_mut(2, intcode, *ip, *relative_base)?;
+    *output = W::from_i128(i128::from(operand1 < operand2)).unwrap_or_default();
+    *ip += 4;
+    Ok(())
+}
+
+pub fn is_equal<W: Word>(
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+) -> Result<(), VmError> {
+    let operand1 = parameters.get(0, intcode, *ip, *relative_base)?;
+    let operand2 = parameters.get(1, intcode, *ip, *relative_base)?;
+    let output = parameters.get_mut(2, intcode, *ip, *relative_base)?;
+    *output = W::from_i128(i128::from(operand1 == operand2)).unwrap_or_default();
+    *ip += 4;
+    Ok(())
+}
+
+pub fn shift_relative_base<W: Word>(
+    parameters: &Parameters,
+    intcode: &mut Memory<W>,
+    ip: &mut usize,
+    relative_base: &mut i128,
+) -> Result<(), VmError> {
+    let operand1 = parameters.get(0, intcode, *ip, *relative_base)?;
+    *relative_base = operand1
+        .to_i128()
+        .and_then(|offset| relative_base.checked_add(offset))
+        .ok_or(VmError::Overflow { ip: *ip })?;
+    *ip += 2;
+    Ok(())
+}
@@ -0,0 +1,387 @@
+use crate::error::VmError;
+use crate::memory::Memory;
+use crate::opcode::{Opcode, ParameterMode};
+use crate::ops;
+use crate::program::{Program, RunState};
+use crate::transpile::{code, size, split_self_modifying};
+use crate::word::Word;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cell {
+    Position(usize),
+    Relative(i128),
+}
+
+// Superinstructions for the common idioms, all behaving exactly like the
+// instruction they replace.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fused {
+    // `add`/`mul` of two immediates, folded
+    Set(Cell, i128),
+    // `add x, #0, y` and `mul x, #1, y`
+    Copy(Cell, Cell),
+    // `jt`/`jf` on an immediate condition, taken or not
+    Jump(usize),
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Instruction {
+    address: usize,
+    opcode: Opcode,
+    fused: Option<Fused>,
+    relative_write: bool,
+}
+
+// A basic block that does not write into itself through a constant address,
+// so only relative writes need checking. It only runs while memory still
+// holds the words it was built from, which is compared again only after a
+// write into the block.
+#[derive(Clone, Debug, PartialEq)]
+struct Region<W> {
+    start: usize,
+    words: Vec<W>,
+    instructions: Vec<Instruction>,
+}
+
+// Code that shares the optimized regions of a program between the runs of
+// it; anything outside them is interpreted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Optimized<W = i128> {
+    regions: Vec<Option<Region<W>>>,
+}
+
+impl Cell {
+    fn address(self, relative_base: i128) -> Option<usize> {
+        match self {
+            Cell::Position(address) => Some(address),
+            Cell::Relative(offset) => relative_base
+                .checked_add(offset)
+                .and_then(|address| usize::try_from(address).ok()),
+        }
+    }
+}
+
+fn fuse(intcode: &[i128], address: usize, opcode: &Opcode) -> Option<Fused> {
+    let operand = |index: usize| intcode[address + index + 1];
+    let immediate = |index| {
+        Some(operand(index)).filter(|_| opcode.parameters.mode(index) == ParameterMode::Immediate)
+    };
+    let cell = |index| match opcode.parameters.mode(index) {
+        ParameterMode::Position => usize::try_from(operand(index)).ok().map(Cell::Position),
+        ParameterMode::Relative => Some(Cell::Relative(operand(index))),
+        ParameterMode::Immediate => None,
+    };
+    match opcode.code {
+        1 | 2 => {
+            let target = cell(2)?;
+            let identity = if opcode.code == 1 { 0 } else { 1 };
+            match (immediate(0), immediate(1)) {
+                (Some(a), Some(b)) if opcode.code == 1 => {
+                    Some(Fused::Set(target, a.checked_add(b)?))
+                }
+                (Some(a), Some(b)) => Some(Fused::Set(target, a.checked_mul(b)?)),
+                (Some(value), None) if value == identity => Some(Fused::Copy(cell(1)?, target)),
+                (None, Some(value)) if value == identity => Some(Fused::Copy(cell(0)?, target)),
+                _ => None,
+            }
+        }
+        5 | 6 => {
+            let condition = immediate(0)?;
+            if (condition != 0) == (opcode.code == 5) {
+                usize::try_from(immediate(1)?).ok().map(Fused::Jump)
+            } else if immediate(1).is_some() || matches!(cell(1), Some(Cell::Position(_))) {
+                // the target is read even when the jump is not taken
+                Some(Fused::Skip)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Fused {
+    // Returns false where the general instruction has to run instead, which
+    // reports errors and handles words that do not fit.
+    fn run<W: Word>(
+        self,
+        program: &mut Program<W>,
+        ip: usize,
+        next: usize,
+    ) -> Result<bool, VmError> {
+        let relative_base = program.relative_base;
+        let (address, value) = match self {
+            Fused::Set(target, value) => match (target.address(relative_base), W::from_i128(value))
+            {
+                (Some(address), Some(value)) => (address, value),
+                _ => return Ok(false),
+            },
+            Fused::Copy(source, target) => {
+                match (source.address(relative_base), target.address(relative_base)) {
+                    (Some(source), Some(address)) => (address, program.intcode.get(source)),
+                    _ => return Ok(false),
+                }
+            }
+            Fused::Jump(target) => {
+                program.ip = target;
+                return Ok(true);
+            }
+            Fused::Skip => {
+                program.ip = next;
+                return Ok(true);
+            }
+        };
+        *program
+            .intcode
+            .get_mut(address)
+            .ok_or(VmError::MemoryLimit { ip, address })? = value;
+        program.ip = next;
+        Ok(true)
+    }
+}
+
+impl<W: Word> Region<W> {
+    fn matches(&self, dense: &[W]) -> bool {
+        dense.get(self.start..self.start + self.words.len()) == Some(&self.words[..])
+    }
+
+    // Only entered when the budget covers the whole region. Stops early
+    // after a write into the rest of the region, so that the next entry
+    // checks it again.
+    fn run(&self, program: &mut Program<W>) -> Result<Option<RunState<W>>, VmError> {
+        let end = self.start + self.words.len();
+        for &Instruction {
+            address,
+            opcode,
+            fused,
+            relative_write,
+        } in &self.instructions
+        {
+            let next = address + size(&opcode);
+            let written = opcode
+                .written_parameter()
+                .filter(|_| relative_write)
+                .and_then(|index| {
+                    opcode.parameters.peek_address(
+                        index,
+                        &program.intcode,
+                        address,
+                        program.relative_base,
+                    )
+                });
+            let applied = match fused {
+                Some(fused) => fused.run(program, address, next)?,
+                None => false,
+            };
+            if !applied {
+                let Program {
+                    intcode,
+                    ip,
+                    relative_base,
+                    overflow,
+                    ..
+                } = program;
+                let parameters = &opcode.parameters;
+                match opcode.code {
+                    1 => ops::add(parameters, intcode, ip, relative_base, *overflow)?,
+                    2 => ops::mul(parameters, intcode, ip, relative_base, *overflow)?,
+                    5 => ops::jump_if(parameters, intcode, ip, relative_base)?,
+                    6 => ops::jump_unless(parameters, intcode, ip, relative_base)?,
+                    7 => ops::is_less_than(parameters, intcode, ip, relative_base)?,
+                    8 => ops::is_equal(parameters, intcode, ip, relative_base)?,
+                    9 => ops::shift_relative_base(parameters, intcode, ip, relative_base)?,
+                    // counts the instruction itself
+                    _ => match program.execute(opcode)? {
+                        Some(state) => return Ok(Some(state)),
+                        None => continue,
+                    },
+                }
+            }
+            program.instructions += 1;
+            if written.is_some_and(|target| (next..end).contains(&target)) {
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<W: Word> Optimized<W> {
+    pub fn new(intcode: &[i128]) -> Self {
+        let code = code(intcode, &[0]);
+        let mut regions = vec![None; intcode.len()];
+        for block in split_self_modifying(intcode, &code, &[0]) {
+            let instructions = block
+                .instructions
+                .iter()
+                .map(|&(address, opcode)| Instruction {
+                    address,
+                    opcode,
+                    fused: fuse(intcode, address, &opcode),
+                    relative_write: opcode.written_parameter().is_some_and(|index| {
+                        opcode.parameters.mode(index) == ParameterMode::Relative
+                    }),
+                })
+                .collect();
+            let words = intcode[block.start..block.end]
+                .iter()
+                .map(|&word| W::from_i128(word))
+                .collect::<Option<_>>();
+            if let Some(words) = words {
+                regions[block.start] = Some(Region {
+                    start: block.start,
+                    words,
+                    instructions,
+                });
+            }
+        }
+        Optimized { regions }
+    }
+
+    pub fn regions(&self) -> usize {
+        self.regions.iter().flatten().count()
+    }
+
+    pub fn superinstructions(&self) -> usize {
+        self.regions
+            .iter()
+            .flatten()
+            .flat_map(|region| &region.instructions)
+            .filter(|instruction| instruction.fused.is_some())
+            .count()
+    }
+
+    fn watch(&self, memory: &mut Memory<W>) {
+        let regions = self
+            .regions
+            .iter()
+            .flatten()
+            .map(|region| {
+                let end = region.start + region.words.len();
+                (region.start, end, !region.matches(memory.dense()))
+            })
+            .collect::<Vec<_>>();
+        memory.watch(regions);
+    }
+
+    // Behaves like `Program::resume`.
+    pub fn resume(&self, program: &mut Program<W>) -> Result<RunState<W>, VmError> {
+        if !program.intcode.is_watching() {
+            self.watch(&mut program.intcode);
+        }
+        loop {
+            if program.budget.is_exhausted(program.instructions) {
+                return Ok(RunState::BudgetExhausted);
+            }
+            let region = self
+                .regions
+                .get(program.ip)
+                .and_then(Option::as_ref)
+                .filter(|region| {
+                    let instructions = region.instructions.len() as u64;
+                    program
+                        .budget
+                        .fits(program.instructions.saturating_add(instructions))
+                })
+                .filter(|region| {
+                    let memory = &mut program.intcode;
+                    if !memory.is_modified(region.start) {
+                        return true;
+                    }
+                    let matches = region.matches(memory.dense());
+                    memory.set_modified(region.start, !matches);
+                    matches
+                });
+            let state = match region {
+                Some(region) => region.run(program)?,
+                None => program.step()?,
+            };
+            if let Some(state) = state {
+                return Ok(state);
+            }
+        }
+    }
+}
+
+impl<W: Word> Program<W> {
+    // Runs through `optimized` whenever neither profiling, history nor
+    // tracing needs to see every instruction.
+    pub fn with_optimized(mut self, optimized: Arc<Optimized<W>>) -> Self {
+        self.optimized = Some(optimized);
+        self
+    }
+}
+
+#[cfg(test)]
+fn assert_equivalent(intcode: &[i128], inputs: &[i128]) {
+    use std::collections::VecDeque;
+
+    let optimized = Arc::new(Optimized::new(intcode));
+    let mut plain = Program::new(intcode, inputs);
+    let mut fast = Program::new(intcode, inputs).with_optimized(optimized);
+    let expected = plain.run(&mut VecDeque::new());
+    assert_eq!(fast.run(&mut VecDeque::new()), expected);
+    assert_eq!(fast.instructions, plain.instructions);
+    assert_eq!(
+        (fast.ip, fast.relative_base),
+        (plain.ip, plain.relative_base)
+    );
+    assert_eq!(fast.intcode.to_vec(), plain.intcode.to_vec());
+}
+
+#[test]
+fn test_superinstructions() {
+    // [15] = 2 + 3, copied to [16] and [17], an always taken and a never
+    // taken jump, then outputs [16] * [17]
+    let intcode = [
+        1101, 2, 3, 15, 1001, 15, 0, 16, 102, 1, 15, 17, 1106, 0, 19, 0, 0, 0, 0, 1105, 0, 99, 2,
+        16, 17, 18, 4, 18, 99,
+    ];
+    let optimized = Optimized::new(&intcode);
+    assert_eq!(optimized.superinstructions(), 5);
+    assert_equivalent(&intcode, &[]);
+    let mut program = Program::new(&intcode, &[]).with_optimized(Arc::new(optimized));
+    assert_eq!(program.next_output(&[]), Ok(Some(25)));
+}
+
+#[test]
+fn test_equivalence() {
+    use crate::transpile::{DAY2_SELF_MODIFYING, DAY5_COMPARE, DAY9_QUINE};
+
+    for input in 6..=10 {
+        assert_equivalent(DAY5_COMPARE, &[input]);
+    }
+    assert_equivalent(DAY9_QUINE, &[]);
+    assert_equivalent(DAY2_SELF_MODIFYING, &[]);
+    // writes a halt over its own `out` through the relative base, inside the
+    // block that runs it
+    let intcode = [109, 10, 21101, 0, 99, -4, 104, 1, 99, 0];
+    assert_equivalent(&intcode, &[]);
+    // copies into the operand of the `out` that follows
+    let intcode = [1105, 1, 4, 0, 1101, 7, 0, 3, 1001, 3, 0, 13, 4, 0, 99];
+    assert_equivalent(&intcode, &[]);
+    // errors and budgets are reported at the same point
+    assert_equivalent(&[21101, 1, 1, -1, 99], &[]);
+    assert_equivalent(&[1106, 0, 3, 3, 0, 99], &[]);
+    let intcode = [1105, 1, 0];
+    let mut program = Program::new(&intcode, &[])
+        .with_instruction_budget(7)
+        .with_optimized(Arc::new(Optimized::new(&intcode)));
+    assert_eq!(program.resume(), Ok(RunState::BudgetExhausted));
+    assert_eq!(program.instructions, 7);
+}
+
+#[test]
+fn test_writes_between_runs() {
+    let intcode = [104, 5, 1105, 1, 0];
+    let mut program =
+        Program::new(&intcode, &[]).with_optimized(Arc::new(Optimized::new(&intcode)));
+    assert_eq!(program.next_output(&[]), Ok(Some(5)));
+    program.intcode[1] = 6;
+    assert_eq!(program.next_output(&[]), Ok(Some(6)));
+    program.intcode.load(&intcode, &[]);
+    assert_eq!(program.next_output(&[]), Ok(Some(5)));
+}
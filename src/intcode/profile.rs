@@ -0,0 +1,282 @@
+use crate::disassembler::{disassemble_from, Disassembly, LineKind};
+use crate::memory::Memory;
+use crate::opcode::{Opcode, ParameterMode};
+use crate::program::Program;
+use crate::symbols::SourceMap;
+use crate::word::Word;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::ops::Range;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub executions: HashMap<usize, u64>,
+    pub opcodes: BTreeMap<usize, u64>,
+    pub reads: HashMap<usize, u64>,
+    pub writes: HashMap<usize, u64>,
+}
+
+fn mnemonic(code: usize) -> String {
+    Opcode {
+        code,
+        parameters: Default::default(),
+    }
+    .mnemonic()
+    .map_or_else(|| code.to_string(), str::to_string)
+}
+
+// Highest counts first, ties by address.
+fn top(counts: &HashMap<usize, u64>, n: usize) -> Vec<(usize, u64)> {
+    let mut counts = counts
+        .iter()
+        .map(|(&address, &count)| (address, count))
+        .collect::<Vec<_>>();
+    counts.sort_unstable_by_key(|&(address, count)| (std::cmp::Reverse(count), address));
+    counts.truncate(n);
+    counts
+}
+
+fn sorted(counts: &HashMap<usize, u64>) -> Vec<(usize, u64)> {
+    let mut counts = counts
+        .iter()
+        .map(|(&address, &count)| (address, count))
+        .collect::<Vec<_>>();
+    counts.sort_unstable();
+    counts
+}
+
+// `  ; loop_start+3` after a row, when a label covers the address.
+fn symbol(symbols: &SourceMap, address: usize) -> String {
+    match symbols.symbol(address) {
+        Some(_) => format!("  ; {}", symbols.locate(address)),
+        None => String::new(),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn json_counts(counts: &[(usize, u64)]) -> String {
+    let counts = counts
+        .iter()
+        .map(|(address, count)| format!("[{}, {}]", address, count))
+        .collect::<Vec<_>>();
+    format!("[{}]", counts.join(", "))
+}
+
+impl Profile {
+    pub fn record<W: Word>(
+        &mut self,
+        opcode: &Opcode,
+        intcode: &Memory<W>,
+        ip: usize,
+        relative_base: i128,
+    ) {
+        *self.executions.entry(ip).or_default() += 1;
+        *self.opcodes.entry(opcode.code).or_default() += 1;
+        let written = opcode.written_parameter();
+        for index in 0..opcode.parameter_count().unwrap_or_default() {
+            if opcode.parameters.mode(index) == ParameterMode::Immediate {
+                continue;
+            }
+            let address = opcode
+                .parameters
+                .peek_address(index, intcode, ip, relative_base);
+            if let Some(address) = address {
+                let cells = if Some(index) == written {
+                    &mut self.writes
+                } else {
+                    &mut self.reads
+                };
+                *cells.entry(address).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.opcodes.values().sum()
+    }
+
+    pub fn hot(&self, n: usize) -> Vec<(usize, u64)> {
+        top(&self.executions, n)
+    }
+
+    // Instructions that did not run, as ranges of addresses. Code is found
+    // statically from address 0 and from everything that did execute.
+    pub fn unexecuted(&self, intcode: &[i128]) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for line in self.disassembly(intcode).lines {
+            if !matches!(line.kind, LineKind::Instruction(_))
+                || self.executions.contains_key(&line.address)
+            {
+                continue;
+            }
+            let end = line.address + line.words.len();
+            match ranges.last_mut() {
+                Some(range) if range.end == line.address => range.end = end,
+                _ => ranges.push(line.address..end),
+            }
+        }
+        ranges
+    }
+
+    fn disassembly(&self, intcode: &[i128]) -> Disassembly {
+        let mut entry_points = self.executions.keys().copied().collect::<Vec<_>>();
+        entry_points.push(0);
+        disassemble_from(intcode, &entry_points)
+    }
+
+    pub fn annotate(&self, intcode: &[i128], symbols: &SourceMap) -> String {
+        let disassembly = self.disassembly(intcode);
+        let mut out = String::new();
+        for line in &disassembly.lines {
+            if let Some(label) = symbols.labels.get(&line.address) {
+                writeln!(out, "{:>16}:", label).unwrap();
+            }
+            let count = match line.kind {
+                LineKind::Instruction(_) => self
+                    .executions
+                    .get(&line.address)
+                    .map_or_else(|| "-".to_string(), u64::to_string),
+                LineKind::Data => String::new(),
+            };
+            writeln!(
+                out,
+                "{:>10} {:>5}: {}",
+                count,
+                line.address,
+                disassembly.source(line)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    pub fn report(&self, intcode: &[i128], symbols: &SourceMap, n: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} instructions", self.instructions()).unwrap();
+        writeln!(out, "\nopcodes:").unwrap();
+        for (&code, count) in &self.opcodes {
+            writeln!(out, "{:>10} {}", count, mnemonic(code)).unwrap();
+        }
+        writeln!(out, "\nhot addresses:").unwrap();
+        let disassembly = self.disassembly(intcode);
+        let source = disassembly
+            .lines
+            .iter()
+            .map(|line| (line.address, disassembly.source(line)))
+            .collect::<HashMap<_, _>>();
+        for (address, count) in self.hot(n) {
+            let source = source.get(&address).map(String::as_str).unwrap_or("?");
+            writeln!(
+                out,
+                "{:>10} {:>5}: {}{}",
+                count,
+                address,
+                source,
+                symbol(symbols, address)
+            )
+            .unwrap();
+        }
+        for (name, cells) in &[("reads", &self.reads), ("writes", &self.writes)] {
+            writeln!(out, "\nmost {}:", name).unwrap();
+            for (address, count) in top(cells, n) {
+                writeln!(
+                    out,
+                    "{:>10} {:>5}{}",
+                    count,
+                    address,
+                    symbol(symbols, address)
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out, "\nnever executed:").unwrap();
+        for range in self.unexecuted(intcode) {
+            writeln!(
+                out,
+                "{:>16}..{}{}",
+                range.start,
+                range.end,
+                symbol(symbols, range.start)
+            )
+            .unwrap();
+        }
+        writeln!(out, "\n{}", self.annotate(intcode, symbols)).unwrap();
+        out
+    }
+
+    pub fn to_json(&self, intcode: &[i128], n: usize) -> String {
+        let opcodes = self
+            .opcodes
+            .iter()
+            .map(|(&code, count)| format!("{}: {}", json_string(&mnemonic(code)), count))
+            .collect::<Vec<_>>();
+        let unexecuted = self
+            .unexecuted(intcode)
+            .iter()
+            .map(|range| format!("[{}, {}]", range.start, range.end))
+            .collect::<Vec<_>>();
+        let mut out = String::new();
+        writeln!(out, "{{").unwrap();
+        writeln!(out, "  \"instructions\": {},", self.instructions()).unwrap();
+        writeln!(out, "  \"opcodes\": {{{}}},", opcodes.join(", ")).unwrap();
+        writeln!(out, "  \"hot\": {},", json_counts(&self.hot(n))).unwrap();
+        writeln!(out, "  \"unexecuted\": [{}],", unexecuted.join(", ")).unwrap();
+        writeln!(
+            out,
+            "  \"executions\": {},",
+            json_counts(&sorted(&self.executions))
+        )
+        .unwrap();
+        writeln!(out, "  \"reads\": {},", json_counts(&sorted(&self.reads))).unwrap();
+        writeln!(out, "  \"writes\": {}", json_counts(&sorted(&self.writes))).unwrap();
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+impl<W: Word> Program<W> {
+    pub fn with_profiling(mut self) -> Self {
+        self.profile = Some(Profile::default());
+        self
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+}
+
+#[test]
+fn test_profile_counts() {
+    use std::collections::VecDeque;
+
+    // counts [19] up to 3 and outputs it, jumping over `out #-1`
+    let intcode = [
+        1001, 19, 1, 19, 1007, 19, 3, 20, 1005, 20, 0, 1005, 19, 16, 104, -1, 4, 19, 99, 0, 0,
+    ];
+    let mut program = Program::new(&intcode, &[]).with_profiling();
+    assert_eq!(program.run(&mut VecDeque::new()), Ok(vec![3]));
+    let profile = program.take_profile().unwrap();
+    assert_eq!(profile.instructions(), program.instructions);
+    assert_eq!(profile.hot(2), vec![(0, 3), (4, 3)]);
+    assert_eq!(profile.executions.get(&16), Some(&1));
+    assert_eq!(profile.opcodes.get(&5), Some(&4));
+    assert_eq!(profile.writes.get(&19), Some(&3));
+    assert_eq!(profile.reads.get(&19), Some(&8));
+    assert_eq!(profile.unexecuted(&intcode), vec![14..16]);
+    assert!(profile
+        .annotate(&intcode, &SourceMap::default())
+        .contains("         -    14: out #-1"));
+    let symbols = SourceMap::new(vec![(4, "check".to_string())].into_iter().collect());
+    assert!(profile
+        .annotate(&intcode, &symbols)
+        .contains("           check:\n         3     4: lt [d19], #3, [d20]"));
+    let report = profile.report(&intcode, &symbols, 2);
+    assert!(report.contains("         3     4: lt [d19], #3, [d20]  ; check\n"));
+    assert!(report.contains("              14..16\n"));
+    assert!(profile
+        .to_json(&intcode, 1)
+        .contains("\"hot\": [[0, 3]],\n  \"unexecuted\": [[14, 16]],"));
+}
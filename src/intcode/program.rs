@@ -0,0 +1,369 @@
+use crate::budget::Budget;
+use crate::device::{InputDevice, OutputDevice};
+use crate::error::VmError;
+use crate::history::{History, Undo};
+use crate::memory::Memory;
+use crate::opcode::Opcode;
+use crate::ops::{
+    add, input, is_equal, is_less_than, jump_if, jump_unless, mul, output, shift_relative_base,
+};
+use crate::optimizer::Optimized;
+use crate::profile::Profile;
+use crate::symbols::SourceMap;
+use crate::word::{Overflow, Word};
+use log::{log_enabled, trace, Level};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunState<W = i128> {
+    Output(W),
+    AwaitingInput,
+    BudgetExhausted,
+    Halted,
+}
+
+#[derive(Clone, Debug)]
+pub struct Program<W = i128> {
+    pub intcode: Memory<W>,
+    pub next_inputs: VecDeque<W>,
+    pub ip: usize,
+    pub relative_base: i128,
+    pub instructions: u64,
+    // set once the halt at `ip` has run, so that resuming does not run it again
+    pub halted: bool,
+    pub budget: Budget,
+    pub overflow: Overflow,
+    pub profile: Option<Profile>,
+    pub history: Option<History<W>>,
+    pub symbols: Option<Arc<SourceMap>>,
+    pub optimized: Option<Arc<Optimized<W>>>,
+}
+
+impl Program {
+    pub fn new(intcode: &[i128], next_inputs: &[i128]) -> Self {
+        Program::from_words(intcode, next_inputs)
+    }
+}
+
+impl<W: Word> Program<W> {
+    pub fn from_words(intcode: &[W], next_inputs: &[W]) -> Self {
+        Self {
+            intcode: Memory::from_words(intcode),
+            next_inputs: next_inputs.iter().cloned().collect(),
+            ip: 0,
+            relative_base: 0,
+            instructions: 0,
+            halted: false,
+            budget: Budget::default(),
+            overflow: Overflow::default(),
+            profile: None,
+            history: None,
+            symbols: None,
+            optimized: None,
+        }
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn push_input(&mut self, value: W) {
+        self.next_inputs.push_back(value);
+    }
+
+    pub fn step(&mut self) -> Result<Option<RunState<W>>, VmError> {
+        if self.budget.is_exhausted(self.instructions) {
+            return Ok(Some(RunState::BudgetExhausted));
+        }
+        if self.ip >= self.intcode.len() {
+            return Err(VmError::RanOffEnd { ip: self.ip });
+        }
+        let opcode = self.intcode.decode(self.ip)?;
+        self.execute(opcode)
+    }
+
+    // Executes an already decoded instruction at ip, as transpiled code does.
+    #[inline]
+    pub fn execute(&mut self, opcode: Opcode) -> Result<Option<RunState<W>>, VmError> {
+        let Self {
+            intcode,
+            next_inputs,
+            ip,
+            relative_base,
+            instructions,
+            halted,
+            overflow,
+            profile,
+            history,
+            symbols,
+            ..
+        } = self;
+        if opcode.code == 99 && *halted {
+            return Ok(Some(RunState::Halted));
+        }
+        if opcode.code == 3 && next_inputs.is_empty() {
+            return Ok(Some(RunState::AwaitingInput));
+        }
+        if let Some(profile) = profile {
+            profile.record(&opcode, intcode, *ip, *relative_base);
+        }
+        let undo = history.as_ref().map(|_| {
+            Undo::new(
+                &opcode,
+                intcode,
+                next_inputs,
+                *instructions,
+                *ip,
+                *relative_base,
+            )
+        });
+        let traced = if log_enabled!(Level::Trace) {
+            Some((
+                symbols
+                    .as_ref()
+                    .map_or_else(|| format!("ip={}", ip), |symbols| symbols.locate(*ip)),
+                *relative_base,
+                intcode.get(*ip),
+                resolve_operands(&opcode, intcode, *ip, *relative_base),
+            ))
+        } else {
+            None
+        };
+        let mut state = None;
+        match opcode.code {
+            1 => add(&opcode.parameters, intcode, ip, relative_base, *overflow)?,
+            2 => mul(&opcode.parameters, intcode, ip, relative_base, *overflow)?,
+            3 => input(next_inputs, &opcode.parameters, intcode, ip, relative_base)?,
+            4 => {
+                let mut output_data = Vec::new();
+                output(
+                    &mut output_data,
+                    &opcode.parameters,
+                    intcode,
+                    ip,
+                    relative_base,
+                )?;
+                state = output_data.pop().map(RunState::Output);
+            }
+            5 => jump_if(&opcode.parameters, intcode, ip, relative_base)?,
+            6 => jump_unless(&opcode.parameters, intcode, ip, relative_base)?,
+            7 => is_less_than(&opcode.parameters, intcode, ip, relative_base)?,
+            8 => is_equal(&opcode.parameters, intcode, ip, relative_base)?,
+            9 => shift_relative_base(&opcode.parameters, intcode, ip, relative_base)?,
+            99 => {
+                *halted = true;
+                state = Some(RunState::Halted);
+            }
+            _ => {
+                return Err(VmError::InvalidOpcode {
+                    ip: *ip,
+                    opcode: intcode[*ip].saturating_i128(),
+                })
+            }
+        }
+        *instructions += 1;
+        if let (Some(history), Some(undo)) = (history, undo) {
+            history.push(undo);
+        }
+        if let Some((location, base, word, (operands, written))) = traced {
+            let mnemonic = opcode.mnemonic().unwrap_or_default();
+            match written {
+                Some(target) => trace!(
+                    "{} rb={} {} {} {:?} [{}] <- {}",
+                    location,
+                    base,
+                    word,
+                    mnemonic,
+                    operands,
+                    target,
+                    intcode.get(target)
+                ),
+                None => trace!(
+                    "{} rb={} {} {} {:?}",
+                    location,
+                    base,
+                    word,
+                    mnemonic,
+                    operands
+                ),
+            }
+        }
+        Ok(state)
+    }
+
+    pub fn resume(&mut self) -> Result<RunState<W>, VmError> {
+        if let Some(optimized) = self.optimized.clone() {
+            if self.profile.is_none() && self.history.is_none() && !log_enabled!(Level::Trace) {
+                return optimized.resume(self);
+            }
+        }
+        loop {
+            if let Some(state) = self.step()? {
+                return Ok(state);
+            }
+        }
+    }
+
+    pub fn next_output(&mut self, next_inputs: &[W]) -> Result<Option<W>, VmError> {
+        self.next_inputs.extend(next_inputs.iter().cloned());
+        self.next_output_from(&mut VecDeque::new())
+    }
+
+    pub fn next_output_from(
+        &mut self,
+        input: &mut impl InputDevice<W>,
+    ) -> Result<Option<W>, VmError> {
+        loop {
+            match self.resume()? {
+                RunState::Output(value) => return Ok(Some(value)),
+                RunState::Halted => return Ok(None),
+                RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: self.ip }),
+                RunState::AwaitingInput => {
+                    let value = input
+                        .read()
+                        .ok_or(VmError::InputStarvation { ip: self.ip })?;
+                    self.push_input(value);
+                }
+            }
+        }
+    }
+
+    pub fn run_with(
+        &mut self,
+        input: &mut impl InputDevice<W>,
+        output: &mut impl OutputDevice<W>,
+    ) -> Result<(), VmError> {
+        while let Some(value) = self.next_output_from(input)? {
+            output.write(value);
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self, input: &mut impl InputDevice<W>) -> Result<Vec<W>, VmError> {
+        let mut outputs = Vec::new();
+        self.run_with(input, &mut outputs)?;
+        Ok(outputs)
+    }
+}
+
+fn resolve_operands<W: Word>(
+    opcode: &Opcode,
+    intcode: &Memory<W>,
+    ip: usize,
+    relative_base: i128,
+) -> (Vec<W>, Option<usize>) {
+    let written = opcode.written_parameter();
+    let operands = (0..opcode.parameter_count().unwrap_or_default())
+        .filter(|&index| Some(index) != written)
+        .filter_map(|index| opcode.parameters.peek(index, intcode, ip, relative_base))
+        .collect();
+    let target = written.and_then(|index| {
+        opcode
+            .parameters
+            .peek_address(index, intcode, ip, relative_base)
+    });
+    (operands, target)
+}
+
+#[test]
+fn test_quine() {
+    let mut program = Program::new(
+        &[
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ],
+        &[1],
+    );
+    let result = program.run(&mut VecDeque::new()).unwrap();
+    assert_eq!(
+        &result,
+        &[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99]
+    );
+}
+
+#[test]
+fn test_large_multiplication() {
+    let mut program = Program::new(&[1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0], &[]);
+    let result = program.run(&mut VecDeque::new()).unwrap();
+    assert_eq!(&result, &[1_219_070_632_396_864]);
+}
+
+#[test]
+fn test_large_output() {
+    let mut program = Program::new(&[104, 1_125_899_906_842_624, 99], &[]);
+    let result = program.run(&mut VecDeque::new()).unwrap();
+    assert_eq!(&result, &[1_125_899_906_842_624]);
+}
+
+#[test]
+fn test_inputs_are_consumed_in_order() {
+    let mut program = Program::new(&[3, 9, 3, 10, 4, 9, 4, 10, 99, 0, 0], &[7]);
+    assert_eq!(
+        program.run(&mut VecDeque::from(vec![8])).unwrap(),
+        vec![7, 8]
+    );
+}
+
+#[test]
+fn test_errors_report_faulting_ip() {
+    let mut program = Program::new(&[1101, 1, 1, 5, 42, 0], &[]);
+    assert_eq!(
+        program.next_output(&[]),
+        Err(VmError::InvalidOpcode { ip: 4, opcode: 42 })
+    );
+    let mut program = Program::new(&[1101, 1, 1, 5, 3, 0, 99], &[]);
+    assert_eq!(
+        program.next_output(&[]),
+        Err(VmError::InputStarvation { ip: 4 })
+    );
+    assert_eq!(program.ip, 4);
+    assert_eq!(program.next_output(&[3]), Ok(None));
+    let mut program = Program::new(&[1, -1, 0, 0, 99], &[]);
+    assert_eq!(
+        program.next_output(&[]),
+        Err(VmError::NegativeAddress { ip: 0, address: -1 })
+    );
+    let mut program = Program::new(&[11101, 1, 1, 0], &[]);
+    assert_eq!(
+        program.next_output(&[]),
+        Err(VmError::WriteToImmediate { ip: 0 })
+    );
+    let mut program = Program::new(&[104, 7], &[]);
+    assert_eq!(program.next_output(&[]), Ok(Some(7)));
+    assert_eq!(program.next_output(&[]), Err(VmError::RanOffEnd { ip: 2 }));
+}
+
+#[test]
+fn test_resume_suspends_on_input() {
+    let mut program = Program::new(&[3, 9, 1001, 9, 1, 9, 4, 9, 99, 0], &[]);
+    assert_eq!(program.resume(), Ok(RunState::AwaitingInput));
+    assert_eq!(program.resume(), Ok(RunState::AwaitingInput));
+    assert_eq!(program.ip, 0);
+    program.push_input(41);
+    assert_eq!(program.resume(), Ok(RunState::Output(42)));
+    assert_eq!(program.resume(), Ok(RunState::Halted));
+    assert_eq!(program.resume(), Ok(RunState::Halted));
+    assert_eq!(program.instructions, 4);
+
+    let mut program = Program::new(&[99], &[]).with_history(16);
+    for _ in 0..3 {
+        assert_eq!(program.resume(), Ok(RunState::Halted));
+    }
+    assert_eq!(program.instructions, 1);
+    assert_eq!(program.history.as_ref().unwrap().undo.len(), 1);
+    assert!(program.reverse_step().is_some());
+    assert_eq!(program.resume(), Ok(RunState::Halted));
+    assert_eq!(program.instructions, 1);
+}
+
+#[test]
+fn test_step() {
+    let mut program = Program::new(&[1101, 2, 3, 5, 104, 0, 99], &[]);
+    assert_eq!(program.step(), Ok(None));
+    assert_eq!(program.ip, 4);
+    assert_eq!(program.intcode[5], 5);
+    assert_eq!(program.step(), Ok(Some(RunState::Output(5))));
+    assert_eq!(program.step(), Ok(Some(RunState::Halted)));
+    assert_eq!(program.ip, 6);
+}
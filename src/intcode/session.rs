@@ -0,0 +1,313 @@
+use crate::device::{InputDevice, OutputDevice};
+use crate::error::VmError;
+use crate::program::{Program, RunState};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+// Each event carries the instruction count at which it happened, so a replay
+// also notices when the same values come out of a different computation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Input { instructions: u64, value: i128 },
+    Output { instructions: u64, value: i128 },
+    Halted { instructions: u64 },
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub events: Vec<Event>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseSessionError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayError {
+    Vm(VmError),
+    Diverged {
+        index: usize,
+        instructions: u64,
+        expected: Event,
+        found: RunState,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input {
+                instructions,
+                value,
+            } => write!(f, "{} in {}", instructions, value),
+            Event::Output {
+                instructions,
+                value,
+            } => write!(f, "{} out {}", instructions, value),
+            Event::Halted { instructions } => write!(f, "{} halt", instructions),
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        let instructions = parts
+            .first()
+            .and_then(|count| count.parse().ok())
+            .ok_or_else(|| "expected an instruction count".to_string())?;
+        let value = || {
+            parts
+                .get(2)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| "expected a value".to_string())
+        };
+        match parts.get(1).copied() {
+            Some("in") if parts.len() == 3 => Ok(Event::Input {
+                instructions,
+                value: value()?,
+            }),
+            Some("out") if parts.len() == 3 => Ok(Event::Output {
+                instructions,
+                value: value()?,
+            }),
+            Some("halt") if parts.len() == 2 => Ok(Event::Halted { instructions }),
+            _ => Err(format!("invalid event `{}`", s.trim())),
+        }
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Session {
+    type Err = ParseSessionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let events = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                line.parse().map_err(|message| ParseSessionError {
+                    line: index + 1,
+                    message,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Session { events })
+    }
+}
+
+impl fmt::Display for ParseSessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid session log, line {}: {}",
+            self.line, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseSessionError {}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Vm(error) => write!(f, "{}", error),
+            ReplayError::Diverged {
+                index,
+                instructions,
+                expected,
+                found,
+            } => {
+                let found = match found {
+                    RunState::Output(value) => format!("out {}", value),
+                    RunState::AwaitingInput => "in".to_string(),
+                    RunState::BudgetExhausted => "budget exhausted".to_string(),
+                    RunState::Halted => "halt".to_string(),
+                };
+                write!(
+                    f,
+                    "replay diverged at event {}: expected `{}`, found `{} {}`",
+                    index, expected, instructions, found
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<VmError> for ReplayError {
+    fn from(error: VmError) -> Self {
+        ReplayError::Vm(error)
+    }
+}
+
+impl Session {
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+impl Program {
+    // Like `run_with`, but logs every input and output to `session`, which
+    // keeps the events up to a failure. Queued inputs are logged when the
+    // program asks for them. Counts are taken after outputs and halts
+    // execute and before inputs do.
+    pub fn record(
+        &mut self,
+        input: &mut impl InputDevice,
+        output: &mut impl OutputDevice,
+        session: &mut Session,
+    ) -> Result<(), VmError> {
+        let mut queued = std::mem::take(&mut self.next_inputs);
+        loop {
+            let state = self.resume()?;
+            let instructions = self.instructions;
+            match state {
+                RunState::Output(value) => {
+                    session.events.push(Event::Output {
+                        instructions,
+                        value,
+                    });
+                    output.write(value);
+                }
+                RunState::Halted => {
+                    session.events.push(Event::Halted { instructions });
+                    return Ok(());
+                }
+                RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: self.ip }),
+                RunState::AwaitingInput => {
+                    let value = queued
+                        .pop_front()
+                        .or_else(|| input.read())
+                        .ok_or(VmError::InputStarvation { ip: self.ip })?;
+                    session.events.push(Event::Input {
+                        instructions,
+                        value,
+                    });
+                    self.push_input(value);
+                }
+            }
+        }
+    }
+
+    // Feeds the logged inputs back and fails on the first event that does
+    // not match, including where in the run it happens. A log cut short by a
+    // failed recording is checked as far as it goes.
+    pub fn replay(&mut self, session: &Session) -> Result<Vec<i128>, ReplayError> {
+        let mut outputs = Vec::new();
+        for (index, &expected) in session.events.iter().enumerate() {
+            let found = self.resume()?;
+            let instructions = self.instructions;
+            let matches = match (expected, found) {
+                (
+                    Event::Input {
+                        instructions: at,
+                        value,
+                    },
+                    RunState::AwaitingInput,
+                ) if at == instructions => {
+                    self.push_input(value);
+                    true
+                }
+                (
+                    Event::Output {
+                        instructions: at,
+                        value,
+                    },
+                    RunState::Output(output),
+                ) => {
+                    outputs.push(output);
+                    at == instructions && value == output
+                }
+                (Event::Halted { instructions: at }, RunState::Halted) => at == instructions,
+                _ => false,
+            };
+            if !matches {
+                return Err(ReplayError::Diverged {
+                    index,
+                    instructions,
+                    expected,
+                    found,
+                });
+            }
+        }
+        Ok(outputs)
+    }
+}
+
+#[test]
+fn test_record_and_replay() {
+    use std::collections::VecDeque;
+
+    // doubles each input until it reads a 0
+    let intcode = [
+        3, 15, 1006, 15, 14, 1002, 15, 2, 16, 4, 16, 1105, 1, 0, 99, 0, 0,
+    ];
+    let mut program = Program::new(&intcode, &[3]);
+    let mut outputs = Vec::new();
+    let mut session = Session::default();
+    program
+        .record(&mut VecDeque::from(vec![5, 0]), &mut outputs, &mut session)
+        .unwrap();
+    assert_eq!(outputs, vec![6, 10]);
+    let log = "0 in 3\n4 out 6\n5 in 5\n9 out 10\n10 in 0\n13 halt\n";
+    assert_eq!(session.to_string(), log);
+    assert_eq!(log.parse(), Ok(session.clone()));
+    assert_eq!(
+        Program::new(&intcode, &[]).replay(&session),
+        Ok(vec![6, 10])
+    );
+
+    let mut tripled = intcode;
+    tripled[7] = 3;
+    assert_eq!(
+        Program::new(&tripled, &[]).replay(&session),
+        Err(ReplayError::Diverged {
+            index: 1,
+            instructions: 4,
+            expected: Event::Output {
+                instructions: 4,
+                value: 6
+            },
+            found: RunState::Output(9),
+        })
+    );
+    assert_eq!(
+        Program::new(&tripled, &[])
+            .replay(&session)
+            .unwrap_err()
+            .to_string(),
+        "replay diverged at event 1: expected `4 out 6`, found `4 out 9`"
+    );
+}
+
+#[test]
+fn test_session_parse_errors() {
+    let error = "0 in 3\n\n4 out x\n".parse::<Session>().unwrap_err();
+    assert_eq!(error.line, 3);
+    assert!("4 jump 1".parse::<Session>().is_err());
+    assert!("halt".parse::<Session>().is_err());
+}
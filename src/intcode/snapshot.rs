@@ -0,0 +1,237 @@
+use crate::program::Program;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub intcode: Arc<[i128]>,
+    pub sparse: Vec<(usize, i128)>,
+    pub ip: usize,
+    pub relative_base: i128,
+    pub instructions: u64,
+    pub halted: bool,
+    pub next_inputs: Vec<i128>,
+    pub outputs: Vec<i128>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseSnapshotError(pub String);
+
+impl fmt::Display for ParseSnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid snapshot: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSnapshotError {}
+
+impl Program {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            intcode: self.intcode.dense().into(),
+            sparse: self.intcode.sparse(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            instructions: self.instructions,
+            halted: self.halted,
+            next_inputs: self.next_inputs.iter().copied().collect(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.intcode.load(&snapshot.intcode, &snapshot.sparse);
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.instructions = snapshot.instructions;
+        self.halted = snapshot.halted;
+        self.next_inputs = snapshot.next_inputs.iter().copied().collect();
+    }
+
+    pub fn fork(&self) -> Program {
+        self.clone()
+    }
+}
+
+impl From<&Snapshot> for Program {
+    fn from(snapshot: &Snapshot) -> Self {
+        let mut program = Program::new(&[], &[]);
+        program.restore(snapshot);
+        program
+    }
+}
+
+impl Snapshot {
+    pub fn with_outputs(mut self, outputs: &[i128]) -> Self {
+        self.outputs = outputs.to_vec();
+        self
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+fn join(values: &[i128]) -> String {
+    values
+        .iter()
+        .map(i128::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ip={}", self.ip)?;
+        writeln!(f, "relative_base={}", self.relative_base)?;
+        writeln!(f, "instructions={}", self.instructions)?;
+        writeln!(f, "halted={}", self.halted)?;
+        writeln!(f, "inputs={}", join(&self.next_inputs))?;
+        writeln!(f, "outputs={}", join(&self.outputs))?;
+        writeln!(f, "memory={}", join(&self.intcode))?;
+        writeln!(
+            f,
+            "sparse={}",
+            self.sparse
+                .iter()
+                .map(|(address, value)| format!("{}:{}", address, value))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = ParseSnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut parts = line.splitn(2, '=');
+                (parts.next().unwrap_or_default().trim(), parts.next())
+            });
+        let mut field = |name: &str| match fields.next() {
+            Some((key, Some(value))) if key == name => Ok(value.trim()),
+            _ => Err(ParseSnapshotError(format!("expected `{}=`", name))),
+        };
+        let number = |name: &str, value: &str| {
+            value
+                .parse()
+                .map_err(|_| ParseSnapshotError(format!("invalid {} `{}`", name, value)))
+        };
+        let list = |name: &str, value: &str| {
+            value
+                .split(',')
+                .filter(|value| !value.is_empty())
+                .map(|value| number(name, value))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let ip = field("ip")?;
+        let ip = ip
+            .parse()
+            .map_err(|_| ParseSnapshotError(format!("invalid ip `{}`", ip)))?;
+        let relative_base = number("relative_base", field("relative_base")?)?;
+        let instructions = field("instructions")?;
+        let instructions = instructions
+            .parse()
+            .map_err(|_| ParseSnapshotError(format!("invalid instructions `{}`", instructions)))?;
+        let halted = field("halted")?;
+        let halted = halted
+            .parse()
+            .map_err(|_| ParseSnapshotError(format!("invalid halted `{}`", halted)))?;
+        let next_inputs = list("inputs", field("inputs")?)?;
+        let outputs = list("outputs", field("outputs")?)?;
+        let intcode = list("memory", field("memory")?)?.into();
+        let sparse = field("sparse")?
+            .split(',')
+            .filter(|cell| !cell.is_empty())
+            .map(|cell| {
+                let mut parts = cell.splitn(2, ':');
+                let address = parts.next().unwrap_or_default();
+                let address = address
+                    .parse()
+                    .map_err(|_| ParseSnapshotError(format!("invalid address `{}`", address)))?;
+                Ok((address, number("sparse", parts.next().unwrap_or_default())?))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Snapshot {
+            intcode,
+            sparse,
+            ip,
+            relative_base,
+            instructions,
+            halted,
+            next_inputs,
+            outputs,
+        })
+    }
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let mut program = Program::new(&[3, 9, 1001, 9, 1, 9, 4, 9, 99, 0], &[41, 7]);
+    program.step().unwrap();
+    let snapshot = program.snapshot().with_outputs(&[-3]);
+    let parsed = snapshot.to_string().parse::<Snapshot>().unwrap();
+    assert_eq!(parsed, snapshot);
+    assert_eq!(parsed.next_inputs, vec![7]);
+    let mut restored = Program::from(&parsed);
+    assert_eq!(restored.next_output(&[]), Ok(Some(42)));
+    assert!("ip=1\nrelative_base=x".parse::<Snapshot>().is_err());
+}
+
+#[test]
+fn test_snapshot_file() {
+    let path = std::env::temp_dir().join(format!("intcode-snapshot-{}.txt", std::process::id()));
+    let mut program = Program::new(&[104, -1, 99], &[]);
+    program.intcode[1 << 40] = 5;
+    let snapshot = program.snapshot();
+    assert_eq!(snapshot.sparse, vec![(1 << 40, 5)]);
+    snapshot.save(&path).unwrap();
+    let loaded = Snapshot::load(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), snapshot);
+    assert_eq!(Program::from(&snapshot).intcode[1 << 40], 5);
+}
+
+#[test]
+fn test_fork_and_restore() {
+    let mut program = Program::new(&[3, 7, 1002, 7, 2, 7, 99, 0], &[]);
+    let snapshot = program.snapshot();
+    let mut fork = program.fork();
+    fork.push_input(5);
+    fork.resume().unwrap();
+    assert_eq!(fork.intcode[7], 10);
+    assert_eq!(program.intcode[7], 0);
+    program.push_input(6);
+    program.resume().unwrap();
+    program.restore(&snapshot);
+    assert_eq!(program.intcode[7], 0);
+    assert_eq!(program.ip, 0);
+}
+
+#[test]
+fn test_restore_halted() {
+    use crate::RunState;
+
+    let mut program = Program::new(&[104, 1, 99], &[]);
+    program.run(&mut std::collections::VecDeque::new()).unwrap();
+    assert_eq!(program.instructions, 2);
+    let snapshot = program.snapshot().to_string().parse::<Snapshot>().unwrap();
+    assert!(snapshot.halted);
+    let mut restored = Program::from(&snapshot);
+    assert_eq!(restored.resume(), Ok(RunState::Halted));
+    assert_eq!(restored.instructions, 2);
+}
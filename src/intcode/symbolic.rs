@@ -0,0 +1,634 @@
+use crate::error::VmError;
+use crate::opcode::{Opcode, ParameterMode};
+use crate::program::Program;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+const MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Const(i128),
+    Symbol(String),
+    // the value at an address that depends on a symbol
+    Load(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    LessThan(Box<Expr>, Box<Expr>),
+    Equals(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Memory(usize),
+    // the nth value read by `in`
+    Input(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unknown {
+    pub name: String,
+    pub source: Source,
+    pub range: RangeInclusive<i128>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    // the cell at an address once the program halts
+    Memory(usize),
+    // the nth output value
+    Output(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolicError {
+    Vm(VmError),
+    SymbolicOpcode { ip: usize },
+    SymbolicWrite { ip: usize },
+    SymbolicBranch { ip: usize },
+    SymbolicRelativeBase { ip: usize },
+    StepLimit { ip: usize },
+    MissingTarget,
+    Nonlinear,
+    NoLinearSolution,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Method {
+    Linear,
+    // why the program could not be solved symbolically
+    Search(SymbolicError),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solution {
+    pub values: Vec<i128>,
+    pub method: Method,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolicProgram {
+    intcode: Vec<i128>,
+    memory: HashMap<usize, Expr>,
+    pub ip: usize,
+    pub relative_base: i128,
+    pub inputs: VecDeque<Expr>,
+    pub outputs: Vec<Expr>,
+    pub steps: u64,
+}
+
+impl Expr {
+    pub fn symbol(name: &str) -> Self {
+        Expr::Symbol(name.to_string())
+    }
+
+    pub fn constant(&self) -> Option<i128> {
+        match self {
+            Expr::Const(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn add(left: Expr, right: Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(a), Some(b)) if a.checked_add(b).is_some() => Expr::Const(a + b),
+            (Some(0), _) => right,
+            (_, Some(0)) => left,
+            _ => Expr::Add(Box::new(left), Box::new(right)),
+        }
+    }
+
+    fn mul(left: Expr, right: Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(a), Some(b)) if a.checked_mul(b).is_some() => Expr::Const(a * b),
+            (Some(0), _) | (_, Some(0)) => Expr::Const(0),
+            (Some(1), _) => right,
+            (_, Some(1)) => left,
+            _ => Expr::Mul(Box::new(left), Box::new(right)),
+        }
+    }
+
+    fn less_than(left: Expr, right: Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(a), Some(b)) => Expr::Const((a < b) as i128),
+            _ => Expr::LessThan(Box::new(left), Box::new(right)),
+        }
+    }
+
+    fn equals(left: Expr, right: Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(a), Some(b)) => Expr::Const((a == b) as i128),
+            _ if left == right => Expr::Const(1),
+            _ => Expr::Equals(Box::new(left), Box::new(right)),
+        }
+    }
+
+    // The expression as `constant + sum(coefficients[i] * names[i])`, if it
+    // is linear in the named symbols.
+    pub fn linear(&self, names: &[&str]) -> Option<(Vec<i128>, i128)> {
+        match self {
+            Expr::Const(value) => Some((vec![0; names.len()], *value)),
+            Expr::Symbol(name) => {
+                let index = names.iter().position(|other| other == name)?;
+                let mut coefficients = vec![0; names.len()];
+                coefficients[index] = 1;
+                Some((coefficients, 0))
+            }
+            Expr::Add(left, right) => {
+                let (mut coefficients, constant) = left.linear(names)?;
+                let (others, other) = right.linear(names)?;
+                for (coefficient, other) in coefficients.iter_mut().zip(others) {
+                    *coefficient = coefficient.checked_add(other)?;
+                }
+                Some((coefficients, constant.checked_add(other)?))
+            }
+            Expr::Mul(left, right) => {
+                let (factor, expr) = match (left.constant(), right.constant()) {
+                    (Some(factor), _) => (factor, right),
+                    (_, Some(factor)) => (factor, left),
+                    _ => return None,
+                };
+                let (coefficients, constant) = expr.linear(names)?;
+                let coefficients = coefficients
+                    .into_iter()
+                    .map(|coefficient| coefficient.checked_mul(factor))
+                    .collect::<Option<_>>()?;
+                Some((coefficients, constant.checked_mul(factor)?))
+            }
+            Expr::Load(_) | Expr::LessThan(..) | Expr::Equals(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Symbol(name) => write!(f, "{}", name),
+            Expr::Load(address) => write!(f, "[{}]", address),
+            Expr::Add(left, right) => write!(f, "({} + {})", left, right),
+            Expr::Mul(left, right) => write!(f, "{} * {}", left, right),
+            Expr::LessThan(left, right) => write!(f, "({} < {})", left, right),
+            Expr::Equals(left, right) => write!(f, "({} == {})", left, right),
+        }
+    }
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Vm(error) => write!(f, "{}", error),
+            SymbolicError::SymbolicOpcode { ip } => write!(f, "symbolic opcode at ip={}", ip),
+            SymbolicError::SymbolicWrite { ip } => {
+                write!(f, "write to a symbolic address at ip={}", ip)
+            }
+            SymbolicError::SymbolicBranch { ip } => {
+                write!(f, "branch on a symbolic condition at ip={}", ip)
+            }
+            SymbolicError::SymbolicRelativeBase { ip } => {
+                write!(f, "symbolic relative base adjustment at ip={}", ip)
+            }
+            SymbolicError::StepLimit { ip } => write!(f, "step limit reached at ip={}", ip),
+            SymbolicError::MissingTarget => write!(f, "the target was never produced"),
+            SymbolicError::Nonlinear => write!(f, "the target is not linear in the unknowns"),
+            SymbolicError::NoLinearSolution => {
+                write!(f, "the linear solution does not hold when run")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SymbolicError {}
+
+impl From<VmError> for SymbolicError {
+    fn from(error: VmError) -> Self {
+        SymbolicError::Vm(error)
+    }
+}
+
+impl SymbolicProgram {
+    pub fn new(intcode: &[i128], inputs: &[Expr]) -> Self {
+        SymbolicProgram {
+            intcode: intcode.to_vec(),
+            inputs: inputs.iter().cloned().collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, address: usize) -> Expr {
+        self.memory
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| Expr::Const(self.intcode.get(address).copied().unwrap_or(0)))
+    }
+
+    pub fn set(&mut self, address: usize, value: Expr) {
+        self.memory.insert(address, value);
+    }
+
+    fn operand(&self, index: usize) -> Expr {
+        self.get(self.ip + index + 1)
+    }
+
+    fn address(&self, opcode: &Opcode, index: usize) -> Result<Expr, SymbolicError> {
+        let operand = self.operand(index);
+        match opcode.parameters.mode(index) {
+            ParameterMode::Position => Ok(operand),
+            ParameterMode::Relative => Ok(Expr::add(Expr::Const(self.relative_base), operand)),
+            ParameterMode::Immediate => Err(VmError::WriteToImmediate { ip: self.ip }.into()),
+        }
+    }
+
+    fn read(&self, opcode: &Opcode, index: usize) -> Result<Expr, SymbolicError> {
+        if opcode.parameters.mode(index) == ParameterMode::Immediate {
+            return Ok(self.operand(index));
+        }
+        match self.address(opcode, index)? {
+            Expr::Const(address) => usize::try_from(address)
+                .map(|address| self.get(address))
+                .map_err(|_| {
+                    VmError::NegativeAddress {
+                        ip: self.ip,
+                        address,
+                    }
+                    .into()
+                }),
+            address => Ok(Expr::Load(Box::new(address))),
+        }
+    }
+
+    fn write(&mut self, opcode: &Opcode, index: usize, value: Expr) -> Result<(), SymbolicError> {
+        let ip = self.ip;
+        let address = self
+            .address(opcode, index)?
+            .constant()
+            .ok_or(SymbolicError::SymbolicWrite { ip })?;
+        let address =
+            usize::try_from(address).map_err(|_| VmError::NegativeAddress { ip, address })?;
+        self.set(address, value);
+        Ok(())
+    }
+
+    fn condition(&self, opcode: &Opcode) -> Result<bool, SymbolicError> {
+        let ip = self.ip;
+        let condition = self.read(opcode, 0)?.constant();
+        condition
+            .map(|condition| condition != 0)
+            .ok_or(SymbolicError::SymbolicBranch { ip })
+    }
+
+    fn jump(&mut self, opcode: &Opcode) -> Result<(), SymbolicError> {
+        let ip = self.ip;
+        let target = self
+            .read(opcode, 1)?
+            .constant()
+            .ok_or(SymbolicError::SymbolicBranch { ip })?;
+        self.ip = usize::try_from(target).map_err(|_| VmError::NegativeAddress {
+            ip,
+            address: target,
+        })?;
+        Ok(())
+    }
+
+    // Runs until the program halts, like `Program::run`, as long as no opcode,
+    // write address, branch or relative base depends on a symbol.
+    pub fn run(&mut self) -> Result<(), SymbolicError> {
+        loop {
+            let ip = self.ip;
+            if self.steps >= MAX_STEPS {
+                return Err(SymbolicError::StepLimit { ip });
+            }
+            self.steps += 1;
+            let word = self
+                .get(ip)
+                .constant()
+                .ok_or(SymbolicError::SymbolicOpcode { ip })?;
+            let opcode = Opcode::new(ip, word)?;
+            let size = opcode.parameter_count().unwrap_or_default() + 1;
+            match opcode.code {
+                1 => {
+                    let value = Expr::add(self.read(&opcode, 0)?, self.read(&opcode, 1)?);
+                    self.write(&opcode, 2, value)?;
+                }
+                2 => {
+                    let value = Expr::mul(self.read(&opcode, 0)?, self.read(&opcode, 1)?);
+                    self.write(&opcode, 2, value)?;
+                }
+                3 => {
+                    let value = self
+                        .inputs
+                        .pop_front()
+                        .ok_or(VmError::InputStarvation { ip })?;
+                    self.write(&opcode, 0, value)?;
+                }
+                4 => {
+                    let value = self.read(&opcode, 0)?;
+                    self.outputs.push(value);
+                }
+                5 | 6 => {
+                    if self.condition(&opcode)? == (opcode.code == 5) {
+                        self.jump(&opcode)?;
+                        continue;
+                    }
+                }
+                7 => {
+                    let value = Expr::less_than(self.read(&opcode, 0)?, self.read(&opcode, 1)?);
+                    self.write(&opcode, 2, value)?;
+                }
+                8 => {
+                    let value = Expr::equals(self.read(&opcode, 0)?, self.read(&opcode, 1)?);
+                    self.write(&opcode, 2, value)?;
+                }
+                9 => {
+                    let shift = self
+                        .read(&opcode, 0)?
+                        .constant()
+                        .ok_or(SymbolicError::SymbolicRelativeBase { ip })?;
+                    self.relative_base = self
+                        .relative_base
+                        .checked_add(shift)
+                        .ok_or(VmError::Overflow { ip })?;
+                }
+                99 => return Ok(()),
+                _ => return Err(VmError::InvalidOpcode { ip, opcode: word }.into()),
+            }
+            self.ip += size;
+        }
+    }
+}
+
+fn inputs_with(inputs: &[i128], unknowns: &[Unknown], values: &[i128]) -> Vec<i128> {
+    let mut inputs = inputs.to_vec();
+    for (unknown, &value) in unknowns.iter().zip(values) {
+        if let Source::Input(index) = unknown.source {
+            if index >= inputs.len() {
+                inputs.resize(index + 1, 0);
+            }
+            inputs[index] = value;
+        }
+    }
+    inputs
+}
+
+fn evaluate(
+    intcode: &[i128],
+    inputs: &[i128],
+    unknowns: &[Unknown],
+    values: &[i128],
+    target: Target,
+) -> Option<i128> {
+    let mut program = Program::new(intcode, &inputs_with(inputs, unknowns, values))
+        .with_instruction_budget(MAX_STEPS);
+    for (unknown, &value) in unknowns.iter().zip(values) {
+        if let Source::Memory(address) = unknown.source {
+            *program.intcode.get_mut(address)? = value;
+        }
+    }
+    let outputs = program.run(&mut VecDeque::new()).ok()?;
+    match target {
+        Target::Memory(address) => Some(program.intcode.get(address)),
+        Target::Output(index) => outputs.get(index).copied(),
+    }
+}
+
+// Calls `found` with every combination of values in lexicographic order
+// until it returns true.
+fn search(
+    ranges: &[RangeInclusive<i128>],
+    found: &mut impl FnMut(&[i128]) -> bool,
+) -> Option<Vec<i128>> {
+    let mut values = ranges
+        .iter()
+        .map(|range| *range.start())
+        .collect::<Vec<_>>();
+    if ranges.iter().any(|range| range.is_empty()) {
+        return None;
+    }
+    loop {
+        if found(&values) {
+            return Some(values);
+        }
+        let mut index = ranges.len();
+        loop {
+            if index == 0 {
+                return None;
+            }
+            index -= 1;
+            if values[index] < *ranges[index].end() {
+                values[index] += 1;
+                break;
+            }
+            values[index] = *ranges[index].start();
+        }
+    }
+}
+
+fn solve_symbolically(
+    intcode: &[i128],
+    inputs: &[i128],
+    unknowns: &[Unknown],
+    target: Target,
+    value: i128,
+) -> Result<Option<Vec<i128>>, SymbolicError> {
+    let mut symbolic_inputs = inputs
+        .iter()
+        .map(|&input| Expr::Const(input))
+        .collect::<Vec<_>>();
+    for unknown in unknowns {
+        if let Source::Input(index) = unknown.source {
+            if index >= symbolic_inputs.len() {
+                symbolic_inputs.resize(index + 1, Expr::Const(0));
+            }
+            symbolic_inputs[index] = Expr::Symbol(unknown.name.clone());
+        }
+    }
+    let mut program = SymbolicProgram::new(intcode, &symbolic_inputs);
+    for unknown in unknowns {
+        if let Source::Memory(address) = unknown.source {
+            program.set(address, Expr::Symbol(unknown.name.clone()));
+        }
+    }
+    program.run()?;
+    let expr = match target {
+        Target::Memory(address) => program.get(address),
+        Target::Output(index) => program
+            .outputs
+            .get(index)
+            .cloned()
+            .ok_or(SymbolicError::MissingTarget)?,
+    };
+    let names = unknowns
+        .iter()
+        .map(|unknown| unknown.name.as_str())
+        .collect::<Vec<_>>();
+    let (coefficients, constant) = expr.linear(&names).ok_or(SymbolicError::Nonlinear)?;
+
+    // Enumerate all but the last unknown that matters and solve for that one.
+    let solved = match coefficients
+        .iter()
+        .rposition(|&coefficient| coefficient != 0)
+    {
+        Some(solved) => solved,
+        None if constant == value => {
+            return Ok(Some(
+                unknowns
+                    .iter()
+                    .map(|unknown| *unknown.range.start())
+                    .collect(),
+            ))
+        }
+        None => return Ok(None),
+    };
+    let ranges = unknowns
+        .iter()
+        .enumerate()
+        .map(|(index, unknown)| {
+            if index == solved || coefficients[index] == 0 {
+                *unknown.range.start()..=*unknown.range.start()
+            } else {
+                unknown.range.clone()
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut solution = None;
+    search(&ranges, &mut |values| {
+        let partial = values
+            .iter()
+            .zip(&coefficients)
+            .enumerate()
+            .filter(|&(index, _)| index != solved)
+            .try_fold(constant, |sum, (_, (value, coefficient))| {
+                sum.checked_add(value.checked_mul(*coefficient)?)
+            });
+        let remainder = match partial.and_then(|partial| value.checked_sub(partial)) {
+            Some(remainder) => remainder,
+            None => return false,
+        };
+        let coefficient = coefficients[solved];
+        let quotient = match remainder.checked_div(coefficient) {
+            Some(quotient) if remainder.checked_rem(coefficient) == Some(0) => quotient,
+            _ => return false,
+        };
+        if !unknowns[solved].range.contains(&quotient) {
+            return false;
+        }
+        let mut values = values.to_vec();
+        values[solved] = quotient;
+        solution = Some(values);
+        true
+    });
+    Ok(solution)
+}
+
+// Finds values for the unknowns that make the program produce `value` at
+// `target`. Straight-line add/mul chains are solved from their linear form,
+// anything else falls back to trying every combination.
+pub fn solve(
+    intcode: &[i128],
+    inputs: &[i128],
+    unknowns: &[Unknown],
+    target: Target,
+    value: i128,
+) -> Option<Solution> {
+    let error = match solve_symbolically(intcode, inputs, unknowns, target, value) {
+        Ok(Some(values)) if evaluate(intcode, inputs, unknowns, &values, target) == Some(value) => {
+            return Some(Solution {
+                values,
+                method: Method::Linear,
+            })
+        }
+        Ok(Some(_)) => SymbolicError::NoLinearSolution,
+        Ok(None) => return None,
+        Err(error) => error,
+    };
+    let ranges = unknowns
+        .iter()
+        .map(|unknown| unknown.range.clone())
+        .collect::<Vec<_>>();
+    search(&ranges, &mut |values| {
+        evaluate(intcode, inputs, unknowns, values, target) == Some(value)
+    })
+    .map(|values| Solution {
+        values,
+        method: Method::Search(error),
+    })
+}
+
+#[cfg(test)]
+fn unknown(name: &str, source: Source, range: RangeInclusive<i128>) -> Unknown {
+    Unknown {
+        name: name.to_string(),
+        source,
+        range,
+    }
+}
+
+#[test]
+fn test_linear_solve() {
+    // [0] = ([1] + [2]) * 7 + 5, like day 2 with the noun and verb
+    let intcode = [1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 17, 3, 1, 3, 18, 0, 99, 7, 5];
+    let mut program = SymbolicProgram::new(&intcode, &[]);
+    program.set(1, Expr::symbol("noun"));
+    program.set(2, Expr::symbol("verb"));
+    program.run().unwrap();
+    assert_eq!(program.get(0).to_string(), "((noun + verb) * 7 + 5)");
+    assert_eq!(
+        program.get(0).linear(&["noun", "verb"]),
+        Some((vec![7, 7], 5))
+    );
+
+    let unknowns = [
+        unknown("noun", Source::Memory(1), 0..=99),
+        unknown("verb", Source::Memory(2), 0..=99),
+    ];
+    assert_eq!(
+        solve(&intcode, &[], &unknowns, Target::Memory(0), 75),
+        Some(Solution {
+            values: vec![0, 10],
+            method: Method::Linear,
+        })
+    );
+    assert_eq!(solve(&intcode, &[], &unknowns, Target::Memory(0), 76), None);
+}
+
+#[test]
+fn test_search_fallback() {
+    let intcode = [3, 9, 1008, 9, 42, 10, 4, 10, 99, 0, 0];
+    let unknowns = [unknown("x", Source::Input(0), 0..=100)];
+    assert_eq!(
+        solve(&intcode, &[], &unknowns, Target::Output(0), 1),
+        Some(Solution {
+            values: vec![42],
+            method: Method::Search(SymbolicError::Nonlinear),
+        })
+    );
+
+    let intcode = [3, 12, 1005, 12, 7, 104, 5, 104, 9, 99, 0, 0, 0];
+    let unknowns = [unknown("x", Source::Input(0), 0..=3)];
+    assert_eq!(
+        solve(&intcode, &[], &unknowns, Target::Output(0), 9),
+        Some(Solution {
+            values: vec![1],
+            method: Method::Search(SymbolicError::SymbolicBranch { ip: 2 }),
+        })
+    );
+}
+
+#[test]
+fn test_overflow() {
+    // [0] = x * -1 has no solution for i128::MIN
+    let intcode = [2, 5, 6, 0, 99, 0, -1];
+    let unknowns = [unknown("x", Source::Memory(5), 0..=3)];
+    assert_eq!(
+        solve(&intcode, &[], &unknowns, Target::Memory(0), i128::MIN),
+        None
+    );
+
+    let mut program = SymbolicProgram::new(&[109, i128::MAX, 109, 1, 99], &[]);
+    assert_eq!(
+        program.run(),
+        Err(SymbolicError::Vm(VmError::Overflow { ip: 2 }))
+    );
+}
@@ -0,0 +1,125 @@
+use crate::error::VmError;
+use crate::program::Program;
+use crate::word::Word;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Labels and source lines by address, as produced by the assembler or kept in
+// a program image, so addresses can be shown as `loop_start+3`. `ends` holds
+// where the instruction or data starting at an address ends; a label or line
+// covers only that item, or just its own address when the end is unknown.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    pub labels: BTreeMap<usize, String>,
+    pub lines: BTreeMap<usize, usize>,
+    pub ends: BTreeMap<usize, usize>,
+}
+
+impl SourceMap {
+    pub fn new(labels: BTreeMap<usize, String>) -> Self {
+        SourceMap {
+            labels,
+            ..SourceMap::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    pub fn address(&self, label: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|(_, name)| name.as_str() == label)
+            .map(|(&address, _)| address)
+    }
+
+    fn covers(&self, start: usize, address: usize) -> bool {
+        address
+            < self
+                .ends
+                .get(&start)
+                .copied()
+                .unwrap_or_else(|| start.saturating_add(1))
+    }
+
+    // The label of the item covering `address` and the offset from it.
+    pub fn symbol(&self, address: usize) -> Option<(&str, usize)> {
+        let (&start, name) = self.labels.range(..=address).next_back()?;
+        Some((name.as_str(), address - start)).filter(|_| self.covers(start, address))
+    }
+
+    // The source line of the item covering `address`.
+    pub fn line(&self, address: usize) -> Option<usize> {
+        let (&start, &line) = self.lines.range(..=address).next_back()?;
+        Some(line).filter(|_| self.covers(start, address))
+    }
+
+    // `loop_start+3 (line 12)`, falling back to `ip=117 (line 14)` or
+    // `ip=117` outside labelled and assembled items.
+    pub fn locate(&self, address: usize) -> String {
+        let mut location = match self.symbol(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("ip={}", address),
+        };
+        if let Some(line) = self.line(address) {
+            location.push_str(&format!(" (line {})", line));
+        }
+        location
+    }
+}
+
+pub(crate) fn locate(symbols: Option<&SourceMap>, address: usize) -> String {
+    match symbols {
+        Some(symbols) => symbols.locate(address),
+        None => format!("ip={}", address),
+    }
+}
+
+impl<W: Word> Program<W> {
+    pub fn with_symbols(mut self, symbols: SourceMap) -> Self {
+        self.symbols = Some(Arc::new(symbols));
+        self
+    }
+
+    pub fn locate(&self, address: usize) -> String {
+        locate(self.symbols.as_deref(), address)
+    }
+
+    // The error message with the faulting ip shown through the symbols.
+    pub fn explain(&self, error: &VmError) -> String {
+        error.message(&self.locate(error.ip()))
+    }
+}
+
+#[test]
+fn test_locate() {
+    let mut symbols = SourceMap::new(
+        vec![(4, "loop_start".to_string()), (10, "done".to_string())]
+            .into_iter()
+            .collect(),
+    );
+    symbols.ends.insert(4, 8);
+    assert_eq!(symbols.locate(2), "ip=2");
+    assert_eq!(symbols.locate(4), "loop_start");
+    assert_eq!(symbols.locate(7), "loop_start+3");
+    assert_eq!(symbols.locate(8), "ip=8");
+    assert_eq!(symbols.locate(10), "done");
+    assert_eq!(symbols.locate(1_000_000), "ip=1000000");
+    assert_eq!(symbols.address("done"), Some(10));
+    symbols.lines.insert(4, 6);
+    symbols.lines.insert(8, 7);
+    symbols.ends.insert(8, 10);
+    assert_eq!(symbols.locate(9), "ip=9 (line 7)");
+    assert_eq!(symbols.line(3), None);
+    assert_eq!(symbols.line(10), None);
+
+    let program = Program::new(&[1, 0, 0, 0, 99], &[]).with_symbols(symbols);
+    let error = VmError::InvalidOpcode { ip: 7, opcode: 42 };
+    assert_eq!(
+        program.explain(&error),
+        "invalid opcode `42` at loop_start+3 (line 6)"
+    );
+    assert_eq!(error.to_string(), "invalid opcode `42` at ip=7");
+}
@@ -0,0 +1,489 @@
+use crate::disassembler::{decode, disassemble_instruction, falls_through, reachable};
+use crate::image::Image;
+use crate::opcode::{Opcode, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+const WORDS_PER_LINE: usize = 12;
+
+pub(crate) struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub instructions: Vec<(usize, Opcode)>,
+}
+
+pub(crate) fn size(opcode: &Opcode) -> usize {
+    opcode.parameter_count().unwrap_or_default() + 1
+}
+
+// Compiled blocks also end at input and output, where `resume` returns.
+fn ends_block(opcode: &Opcode) -> bool {
+    matches!(opcode.code, 3 | 4 | 5 | 6 | 99)
+}
+
+pub(crate) fn ends_control_flow(opcode: &Opcode) -> bool {
+    matches!(opcode.code, 5 | 6 | 99)
+}
+
+fn static_target(intcode: &[i128], address: usize, opcode: &Opcode) -> Option<usize> {
+    let index = opcode.written_parameter()?;
+    match opcode.parameters.mode(index) {
+        ParameterMode::Position => usize::try_from(intcode[address + index + 1]).ok(),
+        _ => None,
+    }
+}
+
+fn immediate_operands<'a>(
+    intcode: &'a [i128],
+    code: &'a BTreeSet<usize>,
+) -> impl Iterator<Item = usize> + 'a {
+    code.iter().flat_map(move |&address| {
+        let opcode = decode(intcode, address).unwrap();
+        (0..opcode.parameter_count().unwrap_or_default())
+            .filter(move |&index| opcode.parameters.mode(index) == ParameterMode::Immediate)
+            .filter_map(move |index| usize::try_from(intcode[address + index + 1]).ok())
+    })
+}
+
+// Code right after an unconditional jump is only reachable through an
+// indirect jump; it is taken as a return site when some instruction pushes
+// its address as an immediate operand.
+pub(crate) fn code(intcode: &[i128], entry_points: &[usize]) -> BTreeSet<usize> {
+    let mut entry_points = entry_points.to_vec();
+    loop {
+        let code = reachable(intcode, &entry_points);
+        let pushed = immediate_operands(intcode, &code).collect::<BTreeSet<_>>();
+        let return_sites = code
+            .iter()
+            .filter_map(|&address| {
+                let opcode = decode(intcode, address).unwrap();
+                let next = address + size(&opcode);
+                let unconditional = matches!(opcode.code, 5 | 6)
+                    && !falls_through(&opcode, &intcode[address + 1..next]);
+                Some(next).filter(|_| unconditional)
+            })
+            .filter(|site| {
+                pushed.contains(site) && !code.contains(site) && decode(intcode, *site).is_some()
+            })
+            .collect::<Vec<_>>();
+        if return_sites.is_empty() {
+            return code;
+        }
+        entry_points.extend(return_sites);
+    }
+}
+
+// An input that suspends is run again from the start of its block, so it
+// starts one wherever it ends one.
+pub(crate) fn leaders(
+    intcode: &[i128],
+    code: &BTreeSet<usize>,
+    entry_points: &[usize],
+    ends: fn(&Opcode) -> bool,
+) -> BTreeSet<usize> {
+    let mut leaders = immediate_operands(intcode, code)
+        .filter(|target| code.contains(target))
+        .collect::<BTreeSet<_>>();
+    leaders.extend(entry_points);
+    for &address in code {
+        let opcode = decode(intcode, address).unwrap();
+        if opcode.code == 3 && ends(&opcode) {
+            leaders.insert(address);
+        }
+        if ends(&opcode) {
+            leaders.insert(address + size(&opcode));
+        }
+    }
+    leaders
+}
+
+pub(crate) fn blocks(
+    intcode: &[i128],
+    code: &BTreeSet<usize>,
+    leaders: &BTreeSet<usize>,
+    ends: fn(&Opcode) -> bool,
+) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for &start in leaders.intersection(code) {
+        let mut instructions = Vec::new();
+        let mut address = start;
+        while code.contains(&address) && (address == start || !leaders.contains(&address)) {
+            let opcode = decode(intcode, address).unwrap();
+            instructions.push((address, opcode));
+            address += size(&opcode);
+            if ends(&opcode) {
+                break;
+            }
+        }
+        blocks.push(Block {
+            start,
+            end: address,
+            instructions,
+        });
+    }
+    blocks
+}
+
+// A write with a constant address into the rest of its own block would leave
+// the block running stale instructions, so the block is split after it and
+// the entry check of the next block catches the change.
+pub(crate) fn split_self_modifying(
+    intcode: &[i128],
+    code: &BTreeSet<usize>,
+    entry_points: &[usize],
+) -> Vec<Block> {
+    let mut leaders = leaders(intcode, code, entry_points, ends_block);
+    for block in blocks(intcode, code, &leaders, ends_block) {
+        for &(address, opcode) in &block.instructions {
+            let next = address + size(&opcode);
+            if static_target(intcode, address, &opcode)
+                .is_some_and(|target| (next..block.end).contains(&target))
+            {
+                leaders.insert(next);
+            }
+        }
+    }
+    blocks(intcode, code, &leaders, ends_block)
+}
+
+fn opcode_constant(word: i128, opcode: &Opcode) -> String {
+    let modes = opcode
+        .parameters
+        .0
+        .iter()
+        .map(|mode| format!("        ParameterMode::{:?},\n", mode))
+        .collect::<String>();
+    format!(
+        "const OP_{}: Opcode = Opcode {{\n    code: {},\n    parameters: Parameters([\n{}    ]),\n}};\n",
+        word, opcode.code, modes
+    )
+}
+
+fn operation(opcode: &Opcode) -> &'static str {
+    match opcode.code {
+        1 => "add",
+        2 => "mul",
+        5 => "jump_if",
+        6 => "jump_unless",
+        7 => "is_less_than",
+        8 => "is_equal",
+        _ => "shift_relative_base",
+    }
+}
+
+fn instruction(
+    out: &mut String,
+    intcode: &[i128],
+    symbols: &BTreeMap<usize, String>,
+    block: &Block,
+    address: usize,
+    opcode: &Opcode,
+) {
+    let word = intcode[address];
+    let next = address + size(opcode);
+    let source = disassemble_instruction(intcode, address).unwrap_or_default();
+    if let Some(name) = symbols.get(&address) {
+        writeln!(out, "                // {}:", name).unwrap();
+    }
+    writeln!(out, "                // {}: {}", address, source).unwrap();
+    let relative_write = opcode
+        .written_parameter()
+        .filter(|&index| opcode.parameters.mode(index) == ParameterMode::Relative)
+        .filter(|_| next < block.end);
+    if let Some(index) = relative_write {
+        writeln!(
+            out,
+            "                let target = OP_{}.parameters.peek_address(
+                    {},
+                    &program.intcode,
+                    program.ip,
+                    program.relative_base,
+                );",
+            word, index
+        )
+        .unwrap();
+    }
+    let arguments = format!(
+        "                    &OP_{}.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+",
+        word
+    );
+    match opcode.code {
+        1 | 2 => writeln!(
+            out,
+            "                ops::{}(\n{}                    program.overflow,\n                )?;",
+            operation(opcode),
+            arguments
+        )
+        .unwrap(),
+        5..=9 => writeln!(
+            out,
+            "                ops::{}(\n{}                )?;",
+            operation(opcode),
+            arguments
+        )
+        .unwrap(),
+        _ => writeln!(
+            out,
+            "                if let Some(state) = program.execute(OP_{})? {{\n                    return Ok(state);\n                }}",
+            word
+        )
+        .unwrap(),
+    }
+    if !matches!(opcode.code, 3 | 4 | 99) {
+        writeln!(out, "                program.instructions += 1;").unwrap();
+    }
+    if relative_write.is_some() {
+        writeln!(
+            out,
+            "                if target.is_some_and(|target| ({}..{}).contains(&target)) {{\n                    continue;\n                }}",
+            next, block.end
+        )
+        .unwrap();
+    }
+}
+
+pub fn transpile(intcode: &[i128]) -> String {
+    transpile_from(intcode, 0, &BTreeMap::new())
+}
+
+// Compiles the code reachable from the image's entry point, with its symbols
+// in the comments.
+pub fn transpile_image(image: &Image) -> String {
+    transpile_from(&image.intcode, image.entry, &image.symbols)
+}
+
+fn transpile_from(intcode: &[i128], entry: usize, symbols: &BTreeMap<usize, String>) -> String {
+    let code = code(intcode, &[entry]);
+    let blocks = split_self_modifying(intcode, &code, &[entry]);
+    let mut out = String::new();
+    if entry == 0 {
+        writeln!(
+            out,
+            "// Generated by intcode-transpile from a {}-word program.",
+            intcode.len()
+        )
+    } else {
+        writeln!(
+            out,
+            "// Generated by intcode-transpile from a {}-word program entered at {}.",
+            intcode.len(),
+            entry
+        )
+    }
+    .unwrap();
+    writeln!(
+        out,
+        "use intcode::{{ops, Opcode, ParameterMode, Parameters, Program, RunState, VmError}};\n"
+    )
+    .unwrap();
+
+    let words = code
+        .iter()
+        .map(|&address| intcode[address])
+        .collect::<BTreeSet<_>>();
+    for &word in &words {
+        let opcode = Opcode::new(0, word).unwrap();
+        writeln!(out, "{}", opcode_constant(word, &opcode)).unwrap();
+    }
+    for block in &blocks {
+        let words = &intcode[block.start..block.end];
+        let line = format!("const BLOCK_{}: &[i128] = &{:?};", block.start, words);
+        if line.len() <= 100 {
+            writeln!(out, "{}\n", line).unwrap();
+        } else {
+            writeln!(out, "const BLOCK_{}: &[i128] = &[", block.start).unwrap();
+            for chunk in words.chunks(WORDS_PER_LINE) {
+                let chunk = chunk.iter().map(i128::to_string).collect::<Vec<_>>();
+                writeln!(out, "    {},", chunk.join(", ")).unwrap();
+            }
+            writeln!(out, "];\n").unwrap();
+        }
+    }
+
+    out.push_str(
+        "// Behaves like `Program::resume`: compiled blocks run while their code is
+// unmodified and the budget covers all of them, everything else is
+// interpreted.
+pub fn resume(program: &mut Program) -> Result<RunState, VmError> {
+    loop {
+        if program.budget.is_exhausted(program.instructions) {
+            return Ok(RunState::BudgetExhausted);
+        }
+        match program.ip {
+",
+    );
+    for block in &blocks {
+        writeln!(
+            out,
+            "            {} if program.intcode.dense().get({}..{}) == Some(BLOCK_{})
+                && program.budget.fits(program.instructions.saturating_add({})) =>
+            {{",
+            block.start,
+            block.start,
+            block.end,
+            block.start,
+            block.instructions.len()
+        )
+        .unwrap();
+        for (address, opcode) in &block.instructions {
+            instruction(&mut out, intcode, symbols, block, *address, opcode);
+        }
+        out.push_str("            }\n");
+    }
+    out.push_str(
+        "            _ => {
+                if let Some(state) = program.step()? {
+                    return Ok(state);
+                }
+            }
+        }
+    }
+}
+
+pub fn run(program: &mut Program) -> Result<Vec<i128>, VmError> {
+    let mut outputs = Vec::new();
+    loop {
+        match resume(program)? {
+            RunState::Output(value) => outputs.push(value),
+            RunState::Halted => return Ok(outputs),
+            RunState::AwaitingInput => return Err(VmError::InputStarvation { ip: program.ip }),
+            RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: program.ip }),
+        }
+    }
+}
+",
+    );
+    out
+}
+
+// Regenerate with `cargo run --bin intcode-transpile <program>` when the
+// output format changes.
+#[cfg(test)]
+mod day5_compare {
+    include!("transpiled/day5_compare.rs");
+}
+
+#[cfg(test)]
+mod day9_quine {
+    include!("transpiled/day9_quine.rs");
+}
+
+#[cfg(test)]
+mod day2_self_modifying {
+    include!("transpiled/day2_self_modifying.rs");
+}
+
+#[cfg(test)]
+mod endless_counter {
+    include!("transpiled/endless_counter.rs");
+}
+
+#[cfg(test)]
+pub(crate) const DAY5_COMPARE: &[i128] = &[
+    3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+    1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105,
+    1, 46, 98, 99,
+];
+
+#[cfg(test)]
+pub(crate) const DAY9_QUINE: &[i128] = &[
+    109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+];
+
+#[cfg(test)]
+pub(crate) const DAY2_SELF_MODIFYING: &[i128] = &[1, 1, 1, 4, 99, 5, 6, 0, 99];
+
+#[cfg(test)]
+pub(crate) const ENDLESS_COUNTER: &[i128] = &[1101, 0, 0, 20, 1001, 20, 1, 20, 1105, 1, 4];
+
+#[test]
+fn test_transpiled_sources_are_up_to_date() {
+    assert_eq!(
+        transpile(DAY5_COMPARE),
+        include_str!("transpiled/day5_compare.rs")
+    );
+    assert_eq!(
+        transpile(DAY9_QUINE),
+        include_str!("transpiled/day9_quine.rs")
+    );
+    assert_eq!(
+        transpile(DAY2_SELF_MODIFYING),
+        include_str!("transpiled/day2_self_modifying.rs")
+    );
+    assert_eq!(
+        transpile(ENDLESS_COUNTER),
+        include_str!("transpiled/endless_counter.rs")
+    );
+}
+
+#[test]
+fn test_transpiled_programs_match_interpreter() {
+    use crate::{Program, VmError};
+    use std::collections::VecDeque;
+
+    type Run = fn(&mut Program) -> Result<Vec<i128>, VmError>;
+    let cases: &[(&[i128], Run, &[i128])] = &[
+        (DAY5_COMPARE, day5_compare::run, &[7, 8, 9]),
+        (DAY9_QUINE, day9_quine::run, &[0]),
+        (DAY2_SELF_MODIFYING, day2_self_modifying::run, &[0]),
+    ];
+    for &(intcode, run, inputs) in cases {
+        for &input in inputs {
+            let mut interpreted = Program::new(intcode, &[input]);
+            let mut compiled = interpreted.fork();
+            assert_eq!(run(&mut compiled), interpreted.run(&mut VecDeque::new()));
+            assert_eq!(compiled.intcode.to_vec(), interpreted.intcode.to_vec());
+            assert_eq!(compiled.ip, interpreted.ip);
+            assert_eq!(compiled.instructions, interpreted.instructions);
+        }
+    }
+    // budgets that run out inside a block stop at the same instruction
+    for &(intcode, run, inputs) in cases {
+        for budget in 0..40 {
+            let mut interpreted =
+                Program::new(intcode, &inputs[..1]).with_instruction_budget(budget);
+            let mut compiled = interpreted.fork();
+            assert_eq!(run(&mut compiled), interpreted.run(&mut VecDeque::new()));
+            assert_eq!(compiled.ip, interpreted.ip);
+            assert_eq!(compiled.instructions, interpreted.instructions);
+        }
+    }
+    // blocks of two instructions never land on a multiple of the deadline
+    // check interval
+    let started = std::time::Instant::now();
+    let mut program =
+        Program::new(ENDLESS_COUNTER, &[]).with_timeout(std::time::Duration::from_millis(50));
+    assert!(matches!(
+        endless_counter::run(&mut program),
+        Err(VmError::BudgetExhausted { .. })
+    ));
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    let mut program = Program::new(DAY2_SELF_MODIFYING, &[]);
+    day2_self_modifying::run(&mut program).unwrap();
+    assert_eq!(
+        program.intcode.to_vec(),
+        Some(vec![30, 1, 1, 4, 2, 5, 6, 0, 99])
+    );
+}
+
+#[test]
+fn test_transpile_image() {
+    let image = "#!entry 3\n#!symbol main 3\n42,0,0,104,7,99"
+        .parse::<Image>()
+        .unwrap();
+    let source = transpile_image(&image);
+    assert!(source
+        .starts_with("// Generated by intcode-transpile from a 6-word program entered at 3.\n"));
+    assert!(
+        source.contains("            {\n                // main:\n                // 3: out #7\n")
+    );
+    assert!(
+        source.contains("            3 if program.intcode.dense().get(3..5) == Some(BLOCK_3)\n")
+    );
+    assert!(!transpile(&image.intcode).contains("BLOCK_3"));
+}
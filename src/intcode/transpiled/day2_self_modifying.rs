@@ -0,0 +1,75 @@
+// Generated by intcode-transpile from a 9-word program.
+use intcode::{ops, Opcode, ParameterMode, Parameters, Program, RunState, VmError};
+
+const OP_1: Opcode = Opcode {
+    code: 1,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_99: Opcode = Opcode {
+    code: 99,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const BLOCK_0: &[i128] = &[1, 1, 1, 4];
+
+const BLOCK_4: &[i128] = &[99];
+
+// Behaves like `Program::resume`: compiled blocks run while their code is
+// unmodified and the budget covers all of them, everything else is
+// interpreted.
+pub fn resume(program: &mut Program) -> Result<RunState, VmError> {
+    loop {
+        if program.budget.is_exhausted(program.instructions) {
+            return Ok(RunState::BudgetExhausted);
+        }
+        match program.ip {
+            0 if program.intcode.dense().get(0..4) == Some(BLOCK_0)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 0: add [1], [1], [4]
+                ops::add(
+                    &OP_1.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                    program.overflow,
+                )?;
+                program.instructions += 1;
+            }
+            4 if program.intcode.dense().get(4..5) == Some(BLOCK_4)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 4: hlt
+                if let Some(state) = program.execute(OP_99)? {
+                    return Ok(state);
+                }
+            }
+            _ => {
+                if let Some(state) = program.step()? {
+                    return Ok(state);
+                }
+            }
+        }
+    }
+}
+
+pub fn run(program: &mut Program) -> Result<Vec<i128>, VmError> {
+    let mut outputs = Vec::new();
+    loop {
+        match resume(program)? {
+            RunState::Output(value) => outputs.push(value),
+            RunState::Halted => return Ok(outputs),
+            RunState::AwaitingInput => return Err(VmError::InputStarvation { ip: program.ip }),
+            RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: program.ip }),
+        }
+    }
+}
@@ -0,0 +1,308 @@
+// Generated by intcode-transpile from a 47-word program.
+use intcode::{ops, Opcode, ParameterMode, Parameters, Program, RunState, VmError};
+
+const OP_3: Opcode = Opcode {
+    code: 3,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_4: Opcode = Opcode {
+    code: 4,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_99: Opcode = Opcode {
+    code: 99,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_104: Opcode = Opcode {
+    code: 4,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_107: Opcode = Opcode {
+    code: 7,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1002: Opcode = Opcode {
+    code: 2,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1005: Opcode = Opcode {
+    code: 5,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1006: Opcode = Opcode {
+    code: 6,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1008: Opcode = Opcode {
+    code: 8,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1101: Opcode = Opcode {
+    code: 1,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1105: Opcode = Opcode {
+    code: 5,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1106: Opcode = Opcode {
+    code: 6,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const BLOCK_0: &[i128] = &[3, 21];
+
+const BLOCK_2: &[i128] = &[1008, 21, 8, 20, 1005, 20, 22];
+
+const BLOCK_9: &[i128] = &[107, 8, 21, 20, 1006, 20, 31];
+
+const BLOCK_16: &[i128] = &[1106, 0, 36];
+
+const BLOCK_22: &[i128] = &[1002, 21, 125, 20, 4, 20];
+
+const BLOCK_28: &[i128] = &[1105, 1, 46];
+
+const BLOCK_31: &[i128] = &[104, 999];
+
+const BLOCK_33: &[i128] = &[1105, 1, 46];
+
+const BLOCK_36: &[i128] = &[1101, 1000, 1, 20, 4, 20];
+
+const BLOCK_42: &[i128] = &[1105, 1, 46];
+
+const BLOCK_46: &[i128] = &[99];
+
+// Behaves like `Program::resume`: compiled blocks run while their code is
+// unmodified and the budget covers all of them, everything else is
+// interpreted.
+pub fn resume(program: &mut Program) -> Result<RunState, VmError> {
+    loop {
+        if program.budget.is_exhausted(program.instructions) {
+            return Ok(RunState::BudgetExhausted);
+        }
+        match program.ip {
+            0 if program.intcode.dense().get(0..2) == Some(BLOCK_0)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 0: in [21]
+                if let Some(state) = program.execute(OP_3)? {
+                    return Ok(state);
+                }
+            }
+            2 if program.intcode.dense().get(2..9) == Some(BLOCK_2)
+                && program.budget.fits(program.instructions.saturating_add(2)) =>
+            {
+                // 2: eq [21], #8, [20]
+                ops::is_equal(
+                    &OP_1008.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+                // 6: jt [20], #22
+                ops::jump_if(
+                    &OP_1005.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            9 if program.intcode.dense().get(9..16) == Some(BLOCK_9)
+                && program.budget.fits(program.instructions.saturating_add(2)) =>
+            {
+                // 9: lt #8, [21], [20]
+                ops::is_less_than(
+                    &OP_107.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+                // 13: jf [20], #31
+                ops::jump_unless(
+                    &OP_1006.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            16 if program.intcode.dense().get(16..19) == Some(BLOCK_16)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 16: jf #0, #36
+                ops::jump_unless(
+                    &OP_1106.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            22 if program.intcode.dense().get(22..28) == Some(BLOCK_22)
+                && program.budget.fits(program.instructions.saturating_add(2)) =>
+            {
+                // 22: mul [21], #125, [20]
+                ops::mul(
+                    &OP_1002.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                    program.overflow,
+                )?;
+                program.instructions += 1;
+                // 26: out [20]
+                if let Some(state) = program.execute(OP_4)? {
+                    return Ok(state);
+                }
+            }
+            28 if program.intcode.dense().get(28..31) == Some(BLOCK_28)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 28: jt #1, #46
+                ops::jump_if(
+                    &OP_1105.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            31 if program.intcode.dense().get(31..33) == Some(BLOCK_31)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 31: out #999
+                if let Some(state) = program.execute(OP_104)? {
+                    return Ok(state);
+                }
+            }
+            33 if program.intcode.dense().get(33..36) == Some(BLOCK_33)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 33: jt #1, #46
+                ops::jump_if(
+                    &OP_1105.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            36 if program.intcode.dense().get(36..42) == Some(BLOCK_36)
+                && program.budget.fits(program.instructions.saturating_add(2)) =>
+            {
+                // 36: add #1000, #1, [20]
+                ops::add(
+                    &OP_1101.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                    program.overflow,
+                )?;
+                program.instructions += 1;
+                // 40: out [20]
+                if let Some(state) = program.execute(OP_4)? {
+                    return Ok(state);
+                }
+            }
+            42 if program.intcode.dense().get(42..45) == Some(BLOCK_42)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 42: jt #1, #46
+                ops::jump_if(
+                    &OP_1105.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            46 if program.intcode.dense().get(46..47) == Some(BLOCK_46)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 46: hlt
+                if let Some(state) = program.execute(OP_99)? {
+                    return Ok(state);
+                }
+            }
+            _ => {
+                if let Some(state) = program.step()? {
+                    return Ok(state);
+                }
+            }
+        }
+    }
+}
+
+pub fn run(program: &mut Program) -> Result<Vec<i128>, VmError> {
+    let mut outputs = Vec::new();
+    loop {
+        match resume(program)? {
+            RunState::Output(value) => outputs.push(value),
+            RunState::Halted => return Ok(outputs),
+            RunState::AwaitingInput => return Err(VmError::InputStarvation { ip: program.ip }),
+            RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: program.ip }),
+        }
+    }
+}
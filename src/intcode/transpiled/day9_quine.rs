@@ -0,0 +1,145 @@
+// Generated by intcode-transpile from a 16-word program.
+use intcode::{ops, Opcode, ParameterMode, Parameters, Program, RunState, VmError};
+
+const OP_99: Opcode = Opcode {
+    code: 99,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_109: Opcode = Opcode {
+    code: 9,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_204: Opcode = Opcode {
+    code: 4,
+    parameters: Parameters([
+        ParameterMode::Relative,
+        ParameterMode::Position,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1001: Opcode = Opcode {
+    code: 1,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1006: Opcode = Opcode {
+    code: 6,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1008: Opcode = Opcode {
+    code: 8,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const BLOCK_0: &[i128] = &[109, 1, 204, -1];
+
+const BLOCK_4: &[i128] = &[1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0];
+
+const BLOCK_15: &[i128] = &[99];
+
+// Behaves like `Program::resume`: compiled blocks run while their code is
+// unmodified and the budget covers all of them, everything else is
+// interpreted.
+pub fn resume(program: &mut Program) -> Result<RunState, VmError> {
+    loop {
+        if program.budget.is_exhausted(program.instructions) {
+            return Ok(RunState::BudgetExhausted);
+        }
+        match program.ip {
+            0 if program.intcode.dense().get(0..4) == Some(BLOCK_0)
+                && program.budget.fits(program.instructions.saturating_add(2)) =>
+            {
+                // 0: arb #1
+                ops::shift_relative_base(
+                    &OP_109.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+                // 2: out rb-1
+                if let Some(state) = program.execute(OP_204)? {
+                    return Ok(state);
+                }
+            }
+            4 if program.intcode.dense().get(4..15) == Some(BLOCK_4)
+                && program.budget.fits(program.instructions.saturating_add(3)) =>
+            {
+                // 4: add [100], #1, [100]
+                ops::add(
+                    &OP_1001.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                    program.overflow,
+                )?;
+                program.instructions += 1;
+                // 8: eq [100], #16, [101]
+                ops::is_equal(
+                    &OP_1008.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+                // 12: jf [101], #0
+                ops::jump_unless(
+                    &OP_1006.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            15 if program.intcode.dense().get(15..16) == Some(BLOCK_15)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 15: hlt
+                if let Some(state) = program.execute(OP_99)? {
+                    return Ok(state);
+                }
+            }
+            _ => {
+                if let Some(state) = program.step()? {
+                    return Ok(state);
+                }
+            }
+        }
+    }
+}
+
+pub fn run(program: &mut Program) -> Result<Vec<i128>, VmError> {
+    let mut outputs = Vec::new();
+    loop {
+        match resume(program)? {
+            RunState::Output(value) => outputs.push(value),
+            RunState::Halted => return Ok(outputs),
+            RunState::AwaitingInput => return Err(VmError::InputStarvation { ip: program.ip }),
+            RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: program.ip }),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Generated by intcode-transpile from a 11-word program.
+use intcode::{ops, Opcode, ParameterMode, Parameters, Program, RunState, VmError};
+
+const OP_1001: Opcode = Opcode {
+    code: 1,
+    parameters: Parameters([
+        ParameterMode::Position,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1101: Opcode = Opcode {
+    code: 1,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const OP_1105: Opcode = Opcode {
+    code: 5,
+    parameters: Parameters([
+        ParameterMode::Immediate,
+        ParameterMode::Immediate,
+        ParameterMode::Position,
+    ]),
+};
+
+const BLOCK_0: &[i128] = &[1101, 0, 0, 20];
+
+const BLOCK_4: &[i128] = &[1001, 20, 1, 20, 1105, 1, 4];
+
+// Behaves like `Program::resume`: compiled blocks run while their code is
+// unmodified and the budget covers all of them, everything else is
+// interpreted.
+pub fn resume(program: &mut Program) -> Result<RunState, VmError> {
+    loop {
+        if program.budget.is_exhausted(program.instructions) {
+            return Ok(RunState::BudgetExhausted);
+        }
+        match program.ip {
+            0 if program.intcode.dense().get(0..4) == Some(BLOCK_0)
+                && program.budget.fits(program.instructions.saturating_add(1)) =>
+            {
+                // 0: add #0, #0, [20]
+                ops::add(
+                    &OP_1101.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                    program.overflow,
+                )?;
+                program.instructions += 1;
+            }
+            4 if program.intcode.dense().get(4..11) == Some(BLOCK_4)
+                && program.budget.fits(program.instructions.saturating_add(2)) =>
+            {
+                // 4: add [20], #1, [20]
+                ops::add(
+                    &OP_1001.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                    program.overflow,
+                )?;
+                program.instructions += 1;
+                // 8: jt #1, #4
+                ops::jump_if(
+                    &OP_1105.parameters,
+                    &mut program.intcode,
+                    &mut program.ip,
+                    &mut program.relative_base,
+                )?;
+                program.instructions += 1;
+            }
+            _ => {
+                if let Some(state) = program.step()? {
+                    return Ok(state);
+                }
+            }
+        }
+    }
+}
+
+pub fn run(program: &mut Program) -> Result<Vec<i128>, VmError> {
+    let mut outputs = Vec::new();
+    loop {
+        match resume(program)? {
+            RunState::Output(value) => outputs.push(value),
+            RunState::Halted => return Ok(outputs),
+            RunState::AwaitingInput => return Err(VmError::InputStarvation { ip: program.ip }),
+            RunState::BudgetExhausted => return Err(VmError::BudgetExhausted { ip: program.ip }),
+        }
+    }
+}
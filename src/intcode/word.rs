@@ -0,0 +1,221 @@
+use crate::device::{InputDevice, InputFn};
+use crate::error::VmError;
+use crate::memory::Memory;
+use crate::program::Program;
+use num_bigint::BigInt;
+use std::convert::TryFrom;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+// What add and mul do when the result does not fit the word.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    #[default]
+    Trap,
+    // Only `run_promoting` carries on with bignum words; everything else,
+    // including `resume`, `run`, `next_output` and networks, stops with
+    // `VmError::Overflow` as under `Trap`.
+    Promote,
+}
+
+pub trait Word:
+    Clone + Debug + Default + Display + FromStr + PartialEq + PartialOrd + Send
+{
+    fn from_i128(value: i128) -> Option<Self>;
+    fn to_i128(&self) -> Option<i128>;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn wrapping_add(&self, other: &Self) -> Self;
+    fn wrapping_mul(&self, other: &Self) -> Self;
+    fn to_bigint(&self) -> BigInt;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // for error messages, which carry plain integers
+    fn saturating_i128(&self) -> i128 {
+        self.to_i128().unwrap_or(if *self < Self::default() {
+            i128::MIN
+        } else {
+            i128::MAX
+        })
+    }
+}
+
+macro_rules! impl_word {
+    ($($word:ty),*) => {
+        $(
+            impl Word for $word {
+                fn from_i128(value: i128) -> Option<Self> {
+                    <$word>::try_from(value).ok()
+                }
+
+                fn to_i128(&self) -> Option<i128> {
+                    Some(i128::from(*self))
+                }
+
+                fn checked_add(&self, other: &Self) -> Option<Self> {
+                    <$word>::checked_add(*self, *other)
+                }
+
+                fn checked_mul(&self, other: &Self) -> Option<Self> {
+                    <$word>::checked_mul(*self, *other)
+                }
+
+                fn wrapping_add(&self, other: &Self) -> Self {
+                    <$word>::wrapping_add(*self, *other)
+                }
+
+                fn wrapping_mul(&self, other: &Self) -> Self {
+                    <$word>::wrapping_mul(*self, *other)
+                }
+
+                fn to_bigint(&self) -> BigInt {
+                    BigInt::from(*self)
+                }
+            }
+        )*
+    };
+}
+
+impl_word!(i64, i128);
+
+impl Word for BigInt {
+    fn from_i128(value: i128) -> Option<Self> {
+        Some(BigInt::from(value))
+    }
+
+    fn to_i128(&self) -> Option<i128> {
+        i128::try_from(self).ok()
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        self.clone()
+    }
+}
+
+impl<W: Word> Program<W> {
+    pub fn promote(&self) -> Program<BigInt> {
+        let mut intcode =
+            Memory::with_limits(&[], self.intcode.dense_limit(), self.intcode.limit());
+        let dense = self
+            .intcode
+            .dense()
+            .iter()
+            .map(W::to_bigint)
+            .collect::<Vec<_>>();
+        let sparse = self
+            .intcode
+            .sparse()
+            .into_iter()
+            .map(|(address, value)| (address, value.to_bigint()))
+            .collect::<Vec<_>>();
+        intcode.load(&dense, &sparse);
+        Program {
+            intcode,
+            next_inputs: self.next_inputs.iter().map(W::to_bigint).collect(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            instructions: self.instructions,
+            halted: self.halted,
+            budget: self.budget,
+            overflow: self.overflow,
+            profile: self.profile.clone(),
+            // undo records hold native words and are not carried over
+            history: None,
+            symbols: self.symbols.clone(),
+            // and neither are optimized regions
+            optimized: None,
+        }
+    }
+
+    // Runs with the native word size and, under `Overflow::Promote`, carries on
+    // with bignum words from the instruction that overflowed.
+    pub fn run_promoting(
+        &mut self,
+        input: &mut impl InputDevice<W>,
+    ) -> Result<Vec<BigInt>, VmError> {
+        let mut outputs = Vec::new();
+        loop {
+            match self.next_output_from(input) {
+                Ok(Some(value)) => outputs.push(value.to_bigint()),
+                Ok(None) => return Ok(outputs),
+                Err(VmError::Overflow { .. }) if self.overflow == Overflow::Promote => {
+                    let mut promoted = self.promote();
+                    let mut input = InputFn(|| input.read().as_ref().map(W::to_bigint));
+                    promoted.run_with(&mut input, &mut outputs)?;
+                    return Ok(outputs);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_words() {
+    assert_eq!(i64::from_i128(1 << 70), None);
+    assert_eq!(Word::checked_mul(&i64::MAX, &2), None);
+    assert_eq!(Word::wrapping_add(&i64::MAX, &1), i64::MIN);
+    assert_eq!(BigInt::from(i128::MAX).saturating_i128(), i128::MAX);
+    assert_eq!(
+        (BigInt::from(i128::MIN) - BigInt::from(1)).saturating_i128(),
+        i128::MIN
+    );
+    assert_eq!(i128::MAX.to_bigint() + 1, BigInt::from(1) << 127);
+}
+
+#[test]
+fn test_overflow_policies() {
+    use std::collections::VecDeque;
+
+    let intcode = [1102, 1 << 62, 4, 7, 4, 7, 99, 0];
+    let mut program = Program::<i64>::from_words(&intcode, &[]);
+    assert_eq!(program.next_output(&[]), Err(VmError::Overflow { ip: 0 }));
+    assert_eq!(program.ip, 0);
+    let mut program = Program::<i64>::from_words(&intcode, &[]).with_overflow(Overflow::Wrap);
+    assert_eq!(program.next_output(&[]), Ok(Some(0)));
+    let mut program = Program::<i64>::from_words(&intcode, &[]).with_overflow(Overflow::Promote);
+    assert_eq!(program.resume(), Err(VmError::Overflow { ip: 0 }));
+    assert_eq!(
+        program.run_promoting(&mut VecDeque::new()),
+        Ok(vec![BigInt::from(1) << 64])
+    );
+    let mut program = Program::<i64>::from_words(&intcode, &[]);
+    assert_eq!(
+        program.run_promoting(&mut VecDeque::new()),
+        Err(VmError::Overflow { ip: 0 })
+    );
+}
+
+#[test]
+fn test_bignum_words() {
+    let intcode = [1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0];
+    let mut program = Program::<i64>::from_words(&intcode, &[]);
+    assert_eq!(program.next_output(&[]), Ok(Some(1_219_070_632_396_864)));
+    let large = BigInt::from(i128::MAX);
+    let intcode = [3, 9, 2, 9, 9, 9, 4, 9, 99, 0]
+        .iter()
+        .map(|&word| BigInt::from(word))
+        .collect::<Vec<_>>();
+    let mut program = Program::from_words(&intcode, std::slice::from_ref(&large));
+    assert_eq!(program.next_output(&[]), Ok(Some(&large * &large)));
+}
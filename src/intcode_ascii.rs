@@ -0,0 +1,23 @@
+use anyhow::{anyhow, Context, Result};
+use intcode::{AsciiReader, AsciiWriter, Image};
+use std::fs;
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .context("usage: intcode-ascii <program> [scripts..]")?;
+    let image = Image::load(&path).with_context(|| format!("cannot load {}", path))?;
+    let mut keyboard = AsciiReader::stdin().with_echo(std::io::stdout());
+    for script in args {
+        let script =
+            fs::read_to_string(&script).with_context(|| format!("cannot read {}", script))?;
+        keyboard = keyboard.with_script(&script);
+    }
+    let mut program = image.program(&[]);
+    program
+        .run_with(&mut keyboard, &mut AsciiWriter::stdout())
+        .map_err(|error| anyhow!(program.explain(&error)))?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Context, Result};
+use intcode::{Image, Opcode, Optimized, ParameterMode, Parameters, Program, RunState, VmError};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const MIN_DURATION: Duration = Duration::from_secs(1);
+
+// The decoder day 9 used before opcodes were decoded arithmetically, kept
+// as the baseline.
+fn decode_string(ip: usize, word: i128) -> Result<Opcode, VmError> {
+    if word < 0 {
+        return Err(VmError::InvalidOpcode { ip, opcode: word });
+    }
+    let mut digits = word.to_string().chars().rev().collect::<Vec<_>>();
+    let code = digits
+        .drain(0..2.min(digits.len()))
+        .collect::<String>()
+        .chars()
+        .rev()
+        .collect::<String>()
+        .parse()
+        .map_err(|_| VmError::InvalidOpcode { ip, opcode: word })?;
+    let modes = digits
+        .iter()
+        .map(|digit| digit.to_digit(10).and_then(ParameterMode::new))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(VmError::BadParameterMode { ip, opcode: word })?;
+    Ok(Opcode {
+        code,
+        parameters: Parameters::new(&modes),
+    })
+}
+
+fn run_string_decoded(program: &mut Program) -> Result<(), VmError> {
+    loop {
+        if program.ip >= program.intcode.len() {
+            return Err(VmError::RanOffEnd { ip: program.ip });
+        }
+        let opcode = decode_string(program.ip, program.intcode.get(program.ip))?;
+        match program.execute(opcode)? {
+            Some(RunState::Halted) => return Ok(()),
+            Some(RunState::AwaitingInput) => {
+                return Err(VmError::InputStarvation { ip: program.ip })
+            }
+            _ => {}
+        }
+    }
+}
+
+fn run(program: &mut Program) -> Result<(), VmError> {
+    program.run(&mut VecDeque::new()).map(|_| ())
+}
+
+fn measure(
+    name: &str,
+    program: &Program,
+    run: fn(&mut Program) -> Result<(), VmError>,
+) -> Result<()> {
+    let mut instructions = 0;
+    let mut runs = 0;
+    let start = Instant::now();
+    while start.elapsed() < MIN_DURATION {
+        let mut program = program.fork();
+        run(&mut program).map_err(|error| anyhow!(program.explain(&error)))?;
+        instructions += program.instructions;
+        runs += 1;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{:<10}{} runs, {} instructions in {:.2}s: {:.1}M instructions/s",
+        name,
+        runs,
+        instructions,
+        elapsed,
+        instructions as f64 / elapsed / 1e6
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .context("usage: intcode-bench <program> [inputs..]")?;
+    let image = Image::load(&path).with_context(|| format!("cannot load {}", path))?;
+    let inputs = args
+        .map(|value| value.parse())
+        .collect::<Result<Vec<i128>, _>>()
+        .context("cannot parse inputs")?;
+    let program = image.program(&inputs);
+    measure("string", &program, run_string_decoded)?;
+    measure("uncached", &program.clone().with_decode_cache(false), run)?;
+    measure("cached", &program, run)?;
+    let optimized = Arc::new(Optimized::new(&image.intcode));
+    measure("optimized", &program.with_optimized(optimized), run)?;
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use anyhow::{Context, Result};
+use intcode::Image;
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .context("usage: intcode-cfg <program> [--dot]")?;
+    let dot = args.next().is_some_and(|flag| flag == "--dot");
+    let image = Image::load(&path).with_context(|| format!("cannot load {}", path))?;
+    let graph = intcode::ControlFlowGraph::from_image(&image);
+    if dot {
+        print!("{}", graph.dot());
+    } else {
+        print!("{}", graph.summary());
+    }
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use anyhow::{Context, Result};
+use intcode::Image;
+use std::path::Path;
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let usage = "usage: intcode-convert <input> <output[.icb]>";
+    let input = args.next().context(usage)?;
+    let output = args.next().context(usage)?;
+    let image = Image::load(&input).with_context(|| format!("cannot load {}", input))?;
+    if Path::new(&output)
+        .extension()
+        .is_some_and(|extension| extension == "icb")
+    {
+        image.save_binary(&output)?;
+    } else {
+        image.save_text(&output)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,566 @@
+use anyhow::{anyhow, bail, Context, Result};
+use intcode::{
+    disassemble_instruction, Image, Opcode, Program, RunState, Snapshot, DEFAULT_HISTORY_LIMIT,
+    MAX_PARAMETERS,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+step [n]             execute n instructions (default 1)
+continue             run until a breakpoint, a watchpoint, input starvation or halt
+output               run until the next output
+reverse-step [n]     undo the last n instructions (default 1)
+reverse-continue [addr]
+                     run backwards to a breakpoint or watchpoint, or to just before
+                     the last write to the memory cell at addr
+who <addr>           show which instruction last wrote the memory cell at addr
+break <addr>         break before executing the instruction at addr (or label)
+break-op <op>        break before executing an opcode (code or mnemonic)
+watch <addr>         stop when the memory cell at addr changes
+delete <addr|mnem>   remove a breakpoint or watchpoint, or an opcode breakpoint by mnemonic
+list                 list breakpoints and watchpoints
+x <addr> [n]         show n memory cells starting at addr
+poke <addr> <value>  write value to the memory cell at addr
+regs                 show ip, relative base and queued inputs
+input <values..>     queue input values
+dis [addr] [n]       disassemble n instructions starting at addr (default ip)
+save <file>          save a snapshot of the machine state to file
+load <file>          restore the machine state from a snapshot file
+quit                 exit the debugger
+an empty line repeats the last command";
+
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Steps,
+    Breakpoint,
+    Watchpoint {
+        address: usize,
+        old: i128,
+        new: i128,
+    },
+    Output(i128),
+    AwaitingInput,
+    BudgetExhausted,
+    Halted,
+    LastWrite(usize),
+    StartOfHistory,
+}
+
+struct Debugger {
+    program: Program,
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<usize, i128>,
+    outputs: Vec<i128>,
+}
+
+impl Debugger {
+    fn new(program: Program) -> Self {
+        Self {
+            program: program.with_history(DEFAULT_HISTORY_LIMIT),
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    // A number or a label from the program's symbols.
+    fn address(&self, argument: Option<&&str>) -> Result<usize> {
+        let argument = argument.context("missing address")?;
+        argument
+            .parse()
+            .ok()
+            .or_else(|| {
+                let symbols = self.program.symbols.as_ref()?;
+                symbols.address(argument)
+            })
+            .ok_or_else(|| anyhow!("invalid address `{}`", argument))
+    }
+
+    fn peek(&self, address: usize) -> i128 {
+        self.program.intcode.get(address)
+    }
+
+    // The instruction at `address` and its size, read through memory so that
+    // sparse addresses decode as well.
+    fn instruction(&self, address: usize) -> Option<(String, usize)> {
+        let words = (0..=MAX_PARAMETERS)
+            .map(|offset| self.peek(address.saturating_add(offset)))
+            .collect::<Vec<_>>();
+        let opcode = intcode::decode(&words, 0)?;
+        let instruction = disassemble_instruction(&words, 0)?;
+        Some((
+            instruction,
+            opcode.parameter_count().unwrap_or_default() + 1,
+        ))
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        let ip = self.program.ip;
+        self.breakpoints.contains(&ip)
+            || Opcode::new(ip, self.peek(ip))
+                .is_ok_and(|opcode| self.opcode_breakpoints.contains(&opcode.code))
+    }
+
+    fn run(&mut self, max_steps: Option<usize>, stop_on_output: bool) -> Result<Stop> {
+        let mut steps = 0;
+        loop {
+            if max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                return Ok(Stop::Steps);
+            }
+            if steps > 0 && self.at_breakpoint() {
+                return Ok(Stop::Breakpoint);
+            }
+            let state = self
+                .program
+                .step()
+                .map_err(|error| anyhow!(self.program.explain(&error)))?;
+            steps += 1;
+            let changed = self.update_watchpoints();
+            match state {
+                Some(RunState::Output(value)) => {
+                    self.outputs.push(value);
+                    if stop_on_output {
+                        return Ok(Stop::Output(value));
+                    }
+                }
+                Some(RunState::AwaitingInput) => return Ok(Stop::AwaitingInput),
+                Some(RunState::BudgetExhausted) => return Ok(Stop::BudgetExhausted),
+                Some(RunState::Halted) => return Ok(Stop::Halted),
+                None => (),
+            }
+            if let Some((address, old, new)) = changed {
+                return Ok(Stop::Watchpoint { address, old, new });
+            }
+        }
+    }
+
+    // Returns the first watched cell that changed as (address, old, new).
+    fn update_watchpoints(&mut self) -> Option<(usize, i128, i128)> {
+        let changed = self
+            .watchpoints
+            .iter()
+            .map(|(&address, &old)| (address, old, self.peek(address)))
+            .find(|&(_, old, new)| old != new);
+        for (&address, value) in self.watchpoints.iter_mut() {
+            *value = self.program.intcode.get(address);
+        }
+        changed
+    }
+
+    fn reverse(&mut self, max_steps: Option<usize>, last_write: Option<usize>) -> Result<Stop> {
+        if let Some(address) = last_write {
+            if self.program.last_write(address).is_none() {
+                bail!("no recorded write to [{}]", address);
+            }
+        }
+        let mut steps = 0;
+        loop {
+            if max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                return Ok(Stop::Steps);
+            }
+            if steps > 0 && self.at_breakpoint() {
+                return Ok(Stop::Breakpoint);
+            }
+            let undo = match self.program.reverse_step() {
+                Some(undo) => undo,
+                None => return Ok(Stop::StartOfHistory),
+            };
+            steps += 1;
+            if undo.opcode.code == 4 {
+                self.outputs.pop();
+            }
+            let changed = self.update_watchpoints();
+            if let Some((address, _)) = undo.write.filter(|write| Some(write.0) == last_write) {
+                return Ok(Stop::LastWrite(address));
+            }
+            if let Some((address, old, new)) = changed {
+                return Ok(Stop::Watchpoint { address, old, new });
+            }
+        }
+    }
+
+    fn location(&self) -> String {
+        let ip = self.program.ip;
+        let instruction = self.instruction(ip).map_or_else(
+            || format!("db {}", self.peek(ip)),
+            |(instruction, _)| instruction,
+        );
+        format!(
+            "{} rb={}  {}",
+            self.program.locate(ip),
+            self.program.relative_base,
+            instruction
+        )
+    }
+
+    fn execute(&mut self, command: &str, out: &mut impl Write) -> Result<bool> {
+        let words = command.split_whitespace().collect::<Vec<_>>();
+        let (name, arguments) = match words.split_first() {
+            Some((name, arguments)) => (*name, arguments),
+            None => return Ok(true),
+        };
+        let stop = match name {
+            "s" | "step" => {
+                let count = arguments.first().map_or(Ok(1), |count| count.parse())?;
+                Some(self.run(Some(count), false)?)
+            }
+            "c" | "continue" => Some(self.run(None, false)?),
+            "o" | "output" => Some(self.run(None, true)?),
+            "rs" | "reverse-step" => {
+                let count = arguments.first().map_or(Ok(1), |count| count.parse())?;
+                Some(self.reverse(Some(count), None)?)
+            }
+            "rc" | "reverse-continue" => {
+                let address = arguments.first().map(|_| self.address(arguments.first()));
+                Some(self.reverse(None, address.transpose()?)?)
+            }
+            "who" => {
+                let address = self.address(arguments.first())?;
+                match self.program.last_write(address) {
+                    Some(undo) => writeln!(
+                        out,
+                        "[{}] last written by {} at {} as instruction {}, was {}",
+                        address,
+                        undo.opcode.mnemonic().unwrap_or_default(),
+                        self.program.locate(undo.ip),
+                        undo.instructions,
+                        undo.write.map_or(0, |write| write.1)
+                    )?,
+                    None => writeln!(out, "no recorded write to [{}]", address)?,
+                }
+                None
+            }
+            "b" | "break" => {
+                self.breakpoints.insert(self.address(arguments.first())?);
+                None
+            }
+            "bo" | "break-op" => {
+                self.opcode_breakpoints
+                    .insert(parse_opcode(arguments.first())?);
+                None
+            }
+            "w" | "watch" => {
+                let address = self.address(arguments.first())?;
+                self.watchpoints.insert(address, self.peek(address));
+                None
+            }
+            "d" | "delete" => {
+                let argument = arguments.first().context("missing argument")?;
+                let removed = match self.address(Some(argument)) {
+                    Ok(address) => {
+                        self.breakpoints.remove(&address)
+                            | self.watchpoints.remove(&address).is_some()
+                    }
+                    Err(_) => self
+                        .opcode_breakpoints
+                        .remove(&parse_opcode(Some(argument))?),
+                };
+                if !removed {
+                    writeln!(out, "nothing to delete at `{}`", argument)?;
+                }
+                None
+            }
+            "l" | "list" => {
+                writeln!(out, "breakpoints: {:?}", self.breakpoints)?;
+                writeln!(out, "opcode breakpoints: {:?}", self.opcode_breakpoints)?;
+                writeln!(out, "watchpoints: {:?}", self.watchpoints)?;
+                None
+            }
+            "x" => {
+                let address = self.address(arguments.first())?;
+                let count = arguments.get(1).map_or(Ok(1), |count| count.parse())?;
+                for address in (0..count).map_while(|offset| address.checked_add(offset)) {
+                    writeln!(out, "{:>6}: {}", address, self.peek(address))?;
+                }
+                None
+            }
+            "p" | "poke" => {
+                let address = self.address(arguments.first())?;
+                let value = arguments.get(1).context("missing value")?.parse()?;
+                *self
+                    .program
+                    .intcode
+                    .get_mut(address)
+                    .context("memory limit exceeded")? = value;
+                if let Some(watched) = self.watchpoints.get_mut(&address) {
+                    *watched = value;
+                }
+                None
+            }
+            "r" | "regs" => {
+                writeln!(out, "{}", self.location())?;
+                writeln!(out, "inputs: {:?}", self.program.next_inputs)?;
+                writeln!(out, "outputs: {:?}", self.outputs)?;
+                None
+            }
+            "i" | "input" => {
+                for value in arguments {
+                    self.program.push_input(value.parse()?);
+                }
+                None
+            }
+            "dis" => {
+                let mut address = match arguments.first() {
+                    Some(_) => self.address(arguments.first())?,
+                    None => self.program.ip,
+                };
+                let count = arguments.get(1).map_or(Ok(10), |count| count.parse())?;
+                for _ in 0..count {
+                    let size = match self.instruction(address) {
+                        Some((instruction, size)) => {
+                            writeln!(out, "{:>6}: {}", address, instruction)?;
+                            size
+                        }
+                        None if address < self.program.intcode.len() => {
+                            writeln!(out, "{:>6}: db {}", address, self.peek(address))?;
+                            1
+                        }
+                        None => break,
+                    };
+                    address = match address.checked_add(size) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                }
+                None
+            }
+            "save" => {
+                let path = arguments.first().context("missing file")?;
+                self.program
+                    .snapshot()
+                    .with_outputs(&self.outputs)
+                    .save(path)?;
+                None
+            }
+            "load" => {
+                let snapshot = Snapshot::load(arguments.first().context("missing file")?)?;
+                self.program.restore(&snapshot);
+                if let Some(history) = &mut self.program.history {
+                    history.undo.clear();
+                }
+                self.outputs = snapshot.outputs;
+                self.update_watchpoints();
+                writeln!(out, "{}", self.location())?;
+                None
+            }
+            "h" | "help" => {
+                writeln!(out, "{}", HELP)?;
+                None
+            }
+            "q" | "quit" => return Ok(false),
+            _ => bail!("unknown command `{}`, try `help`", name),
+        };
+        match stop {
+            Some(Stop::Steps) | Some(Stop::Breakpoint) => (),
+            Some(Stop::Watchpoint { address, old, new }) => {
+                writeln!(out, "watchpoint: [{}] {} -> {}", address, old, new)?
+            }
+            Some(Stop::Output(value)) => writeln!(out, "output: {}", value)?,
+            Some(Stop::AwaitingInput) => writeln!(out, "awaiting input")?,
+            Some(Stop::BudgetExhausted) => writeln!(out, "budget exhausted")?,
+            Some(Stop::Halted) => writeln!(out, "halted, outputs: {:?}", self.outputs)?,
+            Some(Stop::LastWrite(address)) => {
+                writeln!(out, "before the last write to [{}]", address)?
+            }
+            Some(Stop::StartOfHistory) => writeln!(out, "start of history")?,
+            None => return Ok(true),
+        }
+        writeln!(out, "{}", self.location())?;
+        Ok(true)
+    }
+}
+
+fn parse_opcode(argument: Option<&&str>) -> Result<usize> {
+    let argument = argument.context("missing opcode")?;
+    argument
+        .parse()
+        .ok()
+        .or_else(|| intcode::code_from_mnemonic(argument))
+        .ok_or_else(|| anyhow!("unknown opcode `{}`", argument))
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .context("usage: intcode-debug <program> [inputs..]")?;
+    let image = Image::load(&path).with_context(|| format!("cannot load {}", path))?;
+    let inputs = args
+        .map(|value| value.parse())
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid input value")?;
+    let mut debugger = Debugger::new(image.program(&inputs));
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{}", debugger.location())?;
+    let mut last_command = String::new();
+    let stdin = std::io::stdin();
+    loop {
+        write!(out, "(icd) ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if !line.trim().is_empty() {
+            last_command = line.trim().to_string();
+        }
+        match debugger.execute(&last_command, &mut out) {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(error) => writeln!(out, "error: {}", error)?,
+        }
+    }
+}
+
+#[test]
+fn test_breakpoints_and_watchpoints() {
+    let mut debugger = Debugger::new(Program::new(
+        &[1101, 2, 3, 11, 1001, 11, 1, 11, 4, 11, 99, 0],
+        &[],
+    ));
+    let mut out = Vec::new();
+    debugger.execute("break 8", &mut out).unwrap();
+    debugger.execute("watch 11", &mut out).unwrap();
+    assert_eq!(
+        debugger.run(None, false).unwrap(),
+        Stop::Watchpoint {
+            address: 11,
+            old: 0,
+            new: 5
+        }
+    );
+    assert_eq!(debugger.program.ip, 4);
+    debugger.execute("delete 11", &mut out).unwrap();
+    assert_eq!(debugger.run(None, false).unwrap(), Stop::Breakpoint);
+    assert_eq!(debugger.program.ip, 8);
+    assert_eq!(debugger.run(None, true).unwrap(), Stop::Output(6));
+    assert_eq!(debugger.run(None, false).unwrap(), Stop::Halted);
+}
+
+#[test]
+fn test_commands() {
+    let mut debugger = Debugger::new(Program::new(&[3, 7, 1002, 7, 2, 7, 99, 0], &[]));
+    let mut out = Vec::new();
+    debugger.execute("break-op mul", &mut out).unwrap();
+    debugger.execute("continue", &mut out).unwrap();
+    debugger.execute("input 21", &mut out).unwrap();
+    debugger.execute("continue", &mut out).unwrap();
+    assert_eq!(debugger.program.ip, 2);
+    debugger.execute("step", &mut out).unwrap();
+    debugger.execute("poke 0 42", &mut out).unwrap();
+    debugger.execute("x 7", &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("awaiting input"));
+    assert!(out.contains("ip=2 rb=0  mul [7], #2, [7]"));
+    assert!(out.contains("     7: 42"));
+    assert_eq!(debugger.peek(0), 42);
+    assert!(!debugger.execute("quit", &mut Vec::new()).unwrap());
+}
+
+#[test]
+fn test_reverse_debugging() {
+    // adds 5 to [11] twice, then outputs it
+    let mut debugger = Debugger::new(Program::new(
+        &[1001, 11, 5, 11, 1001, 11, 5, 11, 4, 11, 99, 1],
+        &[],
+    ));
+    let mut out = Vec::new();
+    assert_eq!(debugger.run(None, false).unwrap(), Stop::Halted);
+    assert_eq!(debugger.outputs, vec![11]);
+    assert_eq!(debugger.reverse(Some(2), None).unwrap(), Stop::Steps);
+    assert_eq!(debugger.program.ip, 8);
+    assert!(debugger.outputs.is_empty());
+    debugger.execute("who 11", &mut out).unwrap();
+    debugger.execute("reverse-continue 11", &mut out).unwrap();
+    assert_eq!((debugger.program.ip, debugger.peek(11)), (4, 6));
+    debugger.execute("break 4", &mut out).unwrap();
+    assert_eq!(debugger.reverse(None, None).unwrap(), Stop::StartOfHistory);
+    assert_eq!((debugger.program.ip, debugger.peek(11)), (0, 1));
+    assert!(debugger.execute("reverse-continue 12", &mut out).is_err());
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("[11] last written by add at ip=4 as instruction 1, was 6"));
+    assert!(out.contains("before the last write to [11]"));
+}
+
+#[test]
+fn test_symbols() {
+    let image = Image::assemble(
+        "
+        in [counter]
+    loop: add [counter], #-1, [counter]
+        jt [counter], #loop
+        db 42
+    counter: db 0
+    ",
+    )
+    .unwrap();
+    let mut debugger = Debugger::new(image.program(&[1]));
+    let mut out = Vec::new();
+    debugger.execute("break loop", &mut out).unwrap();
+    debugger.execute("continue", &mut out).unwrap();
+    debugger.execute("step 2", &mut out).unwrap();
+    debugger.execute("who counter", &mut out).unwrap();
+    let error = debugger.execute("continue", &mut out).unwrap_err();
+    assert_eq!(error.to_string(), "invalid opcode `42` at ip=9 (line 5)");
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("loop (line 3) rb=0  add [10], #-1, [10]"));
+    assert!(out.contains("ip=9 (line 5) rb=0  db 42"));
+    assert!(out.contains("[10] last written by add at loop (line 3) as instruction 1, was 1"));
+}
+
+#[test]
+fn test_sparse_memory() {
+    let image = Image::assemble(
+        "
+        jt #1, #1099511627776
+    ",
+    )
+    .unwrap();
+    let far = 1 << 40;
+    let mut program = image.program(&[]);
+    program
+        .intcode
+        .load(&image.intcode, &[(far, 104), (far + 1, 7), (far + 2, 99)]);
+    let mut debugger = Debugger::new(program);
+    let mut out = Vec::new();
+    debugger
+        .execute(&format!("dis {} 2", far), &mut out)
+        .unwrap();
+    debugger
+        .execute("x 18446744073709551615 2", &mut out)
+        .unwrap();
+    debugger.execute("step", &mut out).unwrap();
+    debugger.execute("regs", &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(&format!("{}: out #7\n{}: hlt\n", far, far + 2)));
+    assert!(out.contains("18446744073709551615: 0\n"));
+    assert!(out.contains(&format!("ip={} rb=0  out #7", far)));
+}
+
+#[test]
+fn test_labels() {
+    let image = Image::assemble(
+        "
+    start: in [counter]
+        hlt
+    counter: db 0
+    ",
+    )
+    .unwrap();
+    let mut debugger = Debugger::new(image.program(&[1]));
+    let mut out = Vec::new();
+    debugger.execute("break start", &mut out).unwrap();
+    debugger.execute("watch counter", &mut out).unwrap();
+    debugger.execute("delete start", &mut out).unwrap();
+    debugger.execute("delete counter", &mut out).unwrap();
+    assert!(debugger.breakpoints.is_empty() && debugger.watchpoints.is_empty());
+    debugger.execute("dis counter 1", &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(!out.contains("nothing to delete"));
+    assert!(out.contains("     3: db 0\n"));
+}
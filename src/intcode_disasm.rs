@@ -0,0 +1,12 @@
+use anyhow::{Context, Result};
+use intcode::Image;
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let path = std::env::args()
+        .nth(1)
+        .context("usage: intcode-disasm <program>")?;
+    let image = Image::load(&path).with_context(|| format!("cannot load {}", path))?;
+    print!("{}", intcode::disassemble_image(&image));
+    Ok(())
+}
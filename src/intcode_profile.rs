@@ -0,0 +1,36 @@
+use anyhow::{anyhow, Context, Result};
+use intcode::Image;
+use std::collections::VecDeque;
+
+const TOP: usize = 20;
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let json = args.iter().any(|arg| arg == "--json");
+    args.retain(|arg| arg != "--json");
+    let mut args = args.into_iter();
+    let path = args
+        .next()
+        .context("usage: intcode-profile <program> [--json] [inputs..]")?;
+    let image = Image::load(&path).with_context(|| format!("cannot load {}", path))?;
+    let inputs = args
+        .map(|value| value.parse())
+        .collect::<Result<Vec<i128>, _>>()
+        .context("cannot parse inputs")?;
+    let mut program = image.program(&inputs).with_profiling();
+    let outputs = program
+        .run(&mut VecDeque::new())
+        .map_err(|error| anyhow!(program.explain(&error)))?;
+    let profile = program.take_profile().unwrap_or_default();
+    if json {
+        print!("{}", profile.to_json(&image.intcode, TOP));
+    } else {
+        println!("outputs: {:?}\n", outputs);
+        print!(
+            "{}",
+            profile.report(&image.intcode, &image.source_map(), TOP)
+        );
+    }
+    Ok(())
+}